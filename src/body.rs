@@ -0,0 +1,544 @@
+use crate::Progress;
+use bytes::{Bytes, BytesMut};
+use futures::{Async, Future, Poll, Stream};
+use http::HeaderMap;
+use rusoto_core::{signature::SignedRequestPayload, ByteStream};
+use std::{cmp, error::Error, fmt, io, path::PathBuf, vec};
+use tokio_buf::SizeHint;
+use tokio_io::AsyncRead;
+use tower_http::Body;
+
+pub(crate) const DEFAULT_MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Request body handed to the inner service.
+///
+/// Chunks are only produced as the body is polled, so a streaming payload is
+/// not read ahead of the connection, e.g. while an `Expect: 100-continue`
+/// request waits for the server. Signed headers, `Expect` included, are sent
+/// as they are.
+pub struct RusotoBody {
+    kind: BodyKind,
+    max_chunk_size: usize,
+    progress: Option<Progress>,
+    sent: u64,
+}
+
+enum BodyKind {
+    Empty,
+    Buffer(Bytes),
+    Chunks {
+        chunks: vec::IntoIter<Bytes>,
+        len: usize,
+    },
+    Stream {
+        stream: ByteStream,
+        len: Option<usize>,
+    },
+    Reader {
+        reader: Box<dyn AsyncRead + Send>,
+        buf: BytesMut,
+        len: Option<usize>,
+    },
+    Opening(tokio_fs::file::OpenFuture<PathBuf>),
+    Measuring(tokio_fs::file::MetadataFuture),
+}
+
+impl Body for RusotoBody {
+    type Item = io::Cursor<Bytes>;
+    type Error = io::Error;
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let chunk = futures::try_ready!(self.poll_chunk());
+        if let (Some(chunk), Some(progress)) = (&chunk, &self.progress) {
+            self.sent += chunk.get_ref().len() as u64;
+            progress(self.sent);
+        }
+        Ok(Async::Ready(chunk))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+        Ok(Async::Ready(None))
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let mut hint = SizeHint::new();
+        if let Some(len) = self.len() {
+            hint.set_upper(len as u64);
+            hint.set_lower(len as u64);
+        }
+        hint
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.len() == Some(0)
+    }
+}
+
+impl RusotoBody {
+    /// The number of bytes left to send, if it is known up front.
+    pub(crate) fn len(&self) -> Option<usize> {
+        match &self.kind {
+            BodyKind::Empty => Some(0),
+            BodyKind::Buffer(buf) => Some(buf.len()),
+            BodyKind::Chunks { len, .. } => Some(*len),
+            BodyKind::Stream { len, .. } | BodyKind::Reader { len, .. } => *len,
+            BodyKind::Opening(_) | BodyKind::Measuring(_) => None,
+        }
+    }
+
+    /// Create a body with no data, reporting a size of zero.
+    pub fn empty() -> Self {
+        RusotoBody {
+            kind: BodyKind::Empty,
+            max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+            progress: None,
+            sent: 0,
+        }
+    }
+
+    /// Create a body that yields `chunks` in order, as they are.
+    ///
+    /// The chunks are collected up front, so the total length is reported by
+    /// the size hint.
+    pub fn from_chunks<I>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        // A zero length chunk would end a chunked body on the wire.
+        let chunks = chunks
+            .into_iter()
+            .filter(|chunk| !chunk.is_empty())
+            .map(Bytes::from)
+            .collect::<Vec<_>>();
+        let len = chunks.iter().map(Bytes::len).sum();
+
+        RusotoBody {
+            kind: BodyKind::Chunks {
+                chunks: chunks.into_iter(),
+                len,
+            },
+            max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+            progress: None,
+            sent: 0,
+        }
+    }
+
+    /// Create a body from a payload stream.
+    ///
+    /// If `len` is given it is reported by the size hint, so the body can be
+    /// sent with a `Content-Length` rather than chunked. It must match the
+    /// number of bytes the stream yields.
+    pub fn from_stream(stream: ByteStream, len: Option<usize>) -> Self {
+        RusotoBody {
+            kind: BodyKind::Stream { stream, len },
+            max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+            progress: None,
+            sent: 0,
+        }
+    }
+
+    /// Create a body that lazily reads its chunks from `reader` until EOF.
+    pub fn from_reader<R>(reader: R) -> Self
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        RusotoBody {
+            kind: BodyKind::Reader {
+                reader: Box::new(reader),
+                buf: BytesMut::new(),
+                len: None,
+            },
+            max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+            progress: None,
+            sent: 0,
+        }
+    }
+
+    /// Create a body that streams the file at `path`, e.g. for an S3 upload.
+    ///
+    /// The file is opened on the blocking pool once the body is first polled,
+    /// which is when an error opening it is yielded. Its size is only known
+    /// from then on, which is too late for the size hint to set a
+    /// `Content-Length`, so the upload is chunked. Use `open_file` to send
+    /// it with a `Content-Length` instead.
+    pub fn from_file(path: impl Into<PathBuf>) -> Self {
+        RusotoBody {
+            kind: BodyKind::Opening(tokio_fs::File::open(path.into())),
+            max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+            progress: None,
+            sent: 0,
+        }
+    }
+
+    /// Open the file at `path` on the blocking pool and resolve to a body
+    /// that streams it, like `from_file`.
+    ///
+    /// The size of the opened file is reported by the size hint, so the body
+    /// is sent with a `Content-Length` rather than chunked. The file must not
+    /// change size until it has been sent.
+    pub fn open_file(path: impl Into<PathBuf>) -> OpenFile {
+        OpenFile(Some(RusotoBody::from_file(path)))
+    }
+
+    /// Copy a body that is buffered, without its progress callback.
+    pub(crate) fn try_clone(&self) -> Option<Self> {
+        let kind = match &self.kind {
+            BodyKind::Empty => BodyKind::Empty,
+            BodyKind::Buffer(buf) => BodyKind::Buffer(buf.clone()),
+            BodyKind::Chunks { chunks, len } => BodyKind::Chunks {
+                chunks: chunks.clone(),
+                len: *len,
+            },
+            BodyKind::Stream { .. }
+            | BodyKind::Reader { .. }
+            | BodyKind::Opening(_)
+            | BodyKind::Measuring(_) => return None,
+        };
+
+        Some(RusotoBody {
+            kind,
+            max_chunk_size: self.max_chunk_size,
+            progress: None,
+            sent: 0,
+        })
+    }
+
+    pub(crate) fn with_progress(mut self, progress: Option<Progress>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    pub(crate) fn with_max_chunk_size(mut self, max_chunk_size: usize) -> Self {
+        self.max_chunk_size = max_chunk_size;
+        self
+    }
+
+    /// Drive a file body until the file is open and its length is known.
+    fn poll_open(&mut self) -> Poll<(), io::Error> {
+        if let BodyKind::Opening(open) = &mut self.kind {
+            let file = futures::try_ready!(open.poll());
+            self.kind = BodyKind::Measuring(file.metadata());
+        }
+        // The length is read from the opened file, so it can't go stale
+        // between building the body and sending it.
+        if let BodyKind::Measuring(metadata) = &mut self.kind {
+            let (file, metadata) = futures::try_ready!(metadata.poll());
+            self.kind = BodyKind::Reader {
+                reader: Box::new(file),
+                buf: BytesMut::new(),
+                len: Some(metadata.len() as usize),
+            };
+        }
+
+        Ok(Async::Ready(()))
+    }
+
+    fn poll_chunk(&mut self) -> Poll<Option<io::Cursor<Bytes>>, io::Error> {
+        futures::try_ready!(self.poll_open());
+
+        match &mut self.kind {
+            BodyKind::Buffer(buf) => {
+                if !buf.is_empty() {
+                    let len = cmp::min(buf.len(), self.max_chunk_size);
+                    let chunk = io::Cursor::new(buf.split_to(len));
+                    Ok(Async::Ready(Some(chunk)))
+                } else {
+                    Ok(Async::Ready(None))
+                }
+            }
+            BodyKind::Chunks { chunks, len } => match chunks.next() {
+                Some(chunk) => {
+                    *len -= chunk.len();
+                    Ok(Async::Ready(Some(io::Cursor::new(chunk))))
+                }
+                None => Ok(Async::Ready(None)),
+            },
+            // Since rusoto_core 0.40 the payload stream yields `Bytes`, which
+            // are handed on as they are; a `Cursor` over them doesn't copy.
+            BodyKind::Stream { stream, len } => match stream.poll() {
+                Ok(Async::Ready(Some(chunk))) => {
+                    if let Some(len) = len {
+                        *len = len.saturating_sub(chunk.len());
+                    }
+                    Ok(Async::Ready(Some(io::Cursor::new(chunk))))
+                }
+                Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
+                Ok(Async::NotReady) => Ok(Async::NotReady),
+                Err(e) => Err(io::Error::new(e.kind(), PayloadError(e))),
+            },
+            BodyKind::Reader { reader, buf, len } => {
+                // A fresh buffer of exactly `max_chunk_size` bounds the chunk,
+                // `reserve` may hand out more. `read_buf` doesn't zero it.
+                if buf.capacity() < self.max_chunk_size {
+                    *buf = BytesMut::with_capacity(self.max_chunk_size);
+                }
+                match reader.read_buf(buf)? {
+                    Async::Ready(0) => Ok(Async::Ready(None)),
+                    Async::Ready(n) => {
+                        if let Some(len) = len {
+                            *len = len.saturating_sub(n);
+                        }
+                        Ok(Async::Ready(Some(io::Cursor::new(
+                            buf.split_to(n).freeze(),
+                        ))))
+                    }
+                    Async::NotReady => Ok(Async::NotReady),
+                }
+            }
+            BodyKind::Opening(_) | BodyKind::Measuring(_) => unreachable!("opened above"),
+            BodyKind::Empty => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+impl From<Option<SignedRequestPayload>> for RusotoBody {
+    fn from(inner: Option<SignedRequestPayload>) -> Self {
+        match inner {
+            Some(SignedRequestPayload::Buffer(buf)) => RusotoBody {
+                kind: BodyKind::Buffer(buf),
+                max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+                progress: None,
+                sent: 0,
+            },
+            // rusoto knows the length of some streams, e.g. those built with
+            // `ByteStream::new_with_size`.
+            Some(SignedRequestPayload::Stream(stream)) => {
+                let len = stream.size_hint();
+                RusotoBody::from_stream(stream, len)
+            }
+            None => RusotoBody::empty(),
+        }
+    }
+}
+
+impl fmt::Debug for RusotoBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_struct("RusotoBody");
+        match &self.kind {
+            BodyKind::Empty => f.field("kind", &"Empty"),
+            BodyKind::Buffer(buf) => f.field("kind", &"Buffer").field("len", &buf.len()),
+            BodyKind::Chunks { len, .. } => f.field("kind", &"Chunks").field("len", len),
+            BodyKind::Stream { len, .. } => f.field("kind", &"Stream").field("len", len),
+            BodyKind::Reader { len, .. } => f.field("kind", &"Reader").field("len", len),
+            BodyKind::Opening(_) | BodyKind::Measuring(_) => f.field("kind", &"File"),
+        };
+        f.field("max_chunk_size", &self.max_chunk_size).finish()
+    }
+}
+
+/// Future returned by `RusotoBody::open_file`.
+#[derive(Debug)]
+pub struct OpenFile(Option<RusotoBody>);
+
+impl Future for OpenFile {
+    type Item = RusotoBody;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let body = self.0.as_mut().expect("polled after completion");
+        futures::try_ready!(body.poll_open());
+        Ok(Async::Ready(
+            self.0.take().expect("polled after completion"),
+        ))
+    }
+}
+
+/// An error yielded by a `SignedRequestPayload::Stream`, kept as the source so
+/// the original error isn't lost.
+#[derive(Debug)]
+struct PayloadError(io::Error);
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request payload stream failed: {}", self.0)
+    }
+}
+
+impl Error for PayloadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use std::{env, fs, process};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn buffered_payload_is_handed_on_without_copying() {
+        let payload = Bytes::from(vec![7; 1024]);
+        let mut body = RusotoBody::from(Some(SignedRequestPayload::Buffer(payload.clone())));
+
+        let chunk = match body.poll_buf().unwrap() {
+            Async::Ready(Some(chunk)) => chunk.into_inner(),
+            _ => panic!("expected a chunk"),
+        };
+        assert_eq!(chunk.as_ptr(), payload.as_ptr());
+    }
+
+    #[test]
+    fn payload_stream_error_keeps_its_source() {
+        let chunks = vec![
+            Ok(Bytes::from("partial")),
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "disk on fire")),
+        ];
+        let stream = stream::iter_result(chunks);
+        let payload = SignedRequestPayload::Stream(ByteStream::new(stream));
+        let mut body = RusotoBody::from(Some(payload));
+
+        assert!(body.poll_buf().unwrap().is_ready());
+        let err = body.poll_buf().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(
+            err.to_string(),
+            "request payload stream failed: disk on fire"
+        );
+        let source = err.get_ref().and_then(|e| e.source()).unwrap();
+        assert_eq!(source.to_string(), "disk on fire");
+    }
+
+    #[test]
+    fn reader_chunks_arrive_in_order() {
+        let data = (0..6 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut body =
+            RusotoBody::from_reader(io::Cursor::new(data.clone())).with_max_chunk_size(1024);
+
+        let mut received = Vec::new();
+        while let Async::Ready(Some(chunk)) = body.poll_buf().unwrap() {
+            assert!(chunk.get_ref().len() <= 1024);
+            received.extend_from_slice(chunk.get_ref());
+        }
+        assert_eq!(received, data);
+        // EOF stays at the end of the body.
+        assert_eq!(body.poll_buf().unwrap(), Async::Ready(None));
+    }
+
+    #[test]
+    fn size_hint_is_exact_for_buffers_only() {
+        let payload = SignedRequestPayload::Buffer(Bytes::from(vec![0; 1234]));
+        let hint = RusotoBody::from(Some(payload)).size_hint();
+        assert_eq!((hint.lower(), hint.upper()), (1234, Some(1234)));
+
+        let stream = ByteStream::new(stream::iter_ok::<_, io::Error>(vec![Bytes::from("abc")]));
+        let payload = SignedRequestPayload::Stream(stream);
+        let hint = RusotoBody::from(Some(payload)).size_hint();
+        assert_eq!((hint.lower(), hint.upper()), (0, None));
+    }
+
+    fn chunk_sizes(mut body: RusotoBody) -> Vec<usize> {
+        let mut sizes = Vec::new();
+        while let Async::Ready(Some(chunk)) = body.poll_buf().unwrap() {
+            sizes.push(chunk.get_ref().len());
+        }
+        sizes
+    }
+
+    #[test]
+    fn stream_chunk_boundaries_are_kept() {
+        let chunks = vec![
+            Bytes::from(vec![1; 1]),
+            Bytes::from(vec![2; 70_000]),
+            Bytes::from(vec![3; 3]),
+        ];
+        let stream = stream::iter_ok::<_, io::Error>(chunks);
+        let payload = SignedRequestPayload::Stream(ByteStream::new(stream));
+
+        // Stream chunks are not split to `max_chunk_size`, nor merged.
+        let body = RusotoBody::from(Some(payload));
+        assert_eq!(chunk_sizes(body), vec![1, 70_000, 3]);
+    }
+
+    #[test]
+    fn declared_stream_length_is_the_size_hint() {
+        let chunks = stream::iter_ok::<_, io::Error>(vec![Bytes::from("abc"), Bytes::from("de")]);
+        let body = RusotoBody::from_stream(ByteStream::new(chunks), Some(5));
+        let hint = body.size_hint();
+        assert_eq!((hint.lower(), hint.upper()), (5, Some(5)));
+
+        let chunks = stream::iter_ok::<_, io::Error>(vec![Bytes::from("abc")]);
+        let body = RusotoBody::from_stream(ByteStream::new_with_size(chunks, 3), None);
+        assert_eq!(body.size_hint().upper(), None);
+
+        // rusoto's own size is picked up when converting a payload.
+        let chunks = stream::iter_ok::<_, io::Error>(vec![Bytes::from("abc")]);
+        let payload = SignedRequestPayload::Stream(ByteStream::new_with_size(chunks, 3));
+        let mut body = RusotoBody::from(Some(payload));
+        assert_eq!(body.size_hint().upper(), Some(3));
+        body.poll_buf().unwrap();
+        assert_eq!(body.size_hint().upper(), Some(0));
+    }
+
+    #[test]
+    fn file_body_delivers_the_file() {
+        let path = env::temp_dir().join(format!("tower-rusoto-upload-{}", process::id()));
+        let contents = (0..10_000).map(|i| (i % 256) as u8).collect::<Vec<_>>();
+        fs::write(&path, &contents).unwrap();
+
+        let mut body = RusotoBody::from_file(path.clone());
+        let chunks = stream::poll_fn(move || body.poll_buf()).map(io::Cursor::into_inner);
+        let mut rt = Runtime::new().unwrap();
+        let chunks = rt.block_on(chunks.collect()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(chunks.concat(), contents);
+
+        let mut missing = RusotoBody::from_file(path);
+        let polled = rt.block_on(futures::future::poll_fn(move || missing.poll_buf()));
+        assert_eq!(polled.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn chunks_are_delivered_in_order() {
+        let mut body =
+            RusotoBody::from_chunks(vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]);
+        let hint = body.size_hint();
+        assert_eq!((hint.lower(), hint.upper()), (11, Some(11)));
+
+        let mut chunks = Vec::new();
+        while let Async::Ready(Some(chunk)) = body.poll_buf().unwrap() {
+            chunks.push(chunk.into_inner());
+        }
+        assert_eq!(chunks, vec!["one", "two", "three"]);
+        assert_eq!(body.size_hint().upper(), Some(0));
+    }
+
+    #[test]
+    fn reader_chunks_are_bounded() {
+        let reader = io::Cursor::new(vec![7; 150_000]);
+        let body = RusotoBody::from_reader(reader).with_max_chunk_size(64 * 1024);
+
+        assert_eq!(chunk_sizes(body), vec![65_536, 65_536, 18_928]);
+    }
+
+    #[test]
+    fn buffer_is_split_into_chunks() {
+        let payload = SignedRequestPayload::Buffer(Bytes::from(vec![0; 200 * 1024]));
+        let body = RusotoBody::from(Some(payload));
+
+        assert_eq!(chunk_sizes(body), vec![65_536, 65_536, 65_536, 8_192]);
+    }
+
+    #[test]
+    fn file_length_is_read_once_opened() {
+        let path = env::temp_dir().join(format!("tower-rusoto-body-{}", process::id()));
+        fs::write(&path, vec![7; 100_000]).unwrap();
+
+        let mut body = RusotoBody::from_file(path.clone());
+        assert_eq!(body.len(), None);
+
+        // The blocking pool needs the threaded runtime.
+        let chunks = stream::poll_fn(move || {
+            let chunk = futures::try_ready!(body.poll_buf());
+            Ok(Async::Ready(
+                chunk.map(|chunk| (chunk.get_ref().len(), body.len())),
+            ))
+        });
+        let mut rt = Runtime::new().unwrap();
+        let chunks = rt.block_on(chunks.collect()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(chunks, vec![(65_536, Some(34_464)), (34_464, Some(0))]);
+    }
+}
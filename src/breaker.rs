@@ -0,0 +1,314 @@
+//! A circuit breaker that fails fast while AWS is throttling.
+
+use crate::error::{self, Kind};
+use futures::{Async, Future, Poll};
+use http::StatusCode;
+use rusoto_core::{
+    request::{HttpDispatchError, HttpResponse},
+    signature::SignedRequest,
+};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Error types AWS uses to signal throttling in the `x-amzn-ErrorType` header.
+const THROTTLE_ERROR_TYPES: &[&str] = &[
+    "ProvisionedThroughputExceededException",
+    "RequestLimitExceeded",
+    "ThrottlingException",
+    "TooManyRequestsException",
+];
+
+/// Wraps a service in a `CircuitBreaker`.
+#[derive(Clone, Copy, Debug)]
+pub struct CircuitBreakerLayer {
+    settings: Settings,
+}
+
+/// Opens once `threshold` throttled responses arrive within `window`, and then
+/// fails every request right away until `cooldown` has passed.
+///
+/// A response counts as throttled if it is a `503` or `429`, or carries one
+/// of the throttling error types in `x-amzn-ErrorType`. So does a dispatch
+/// failed on its status by `treat_5xx_as_error` or a `status_policy`, since
+/// the error no longer carries the response to look at. The counts are
+/// shared between clones.
+#[derive(Clone)]
+pub struct CircuitBreaker<S> {
+    inner: S,
+    settings: Settings,
+    state: Arc<Mutex<State>>,
+}
+
+/// Response future of a `CircuitBreaker`.
+pub struct ResponseFuture<F> {
+    inner: Option<F>,
+    error: Option<HttpDispatchError>,
+    settings: Settings,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Settings {
+    threshold: usize,
+    window: Duration,
+    cooldown: Duration,
+}
+
+struct State {
+    throttles: VecDeque<Instant>,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreakerLayer {
+    pub fn new(threshold: usize, window: Duration, cooldown: Duration) -> Self {
+        assert!(threshold > 0, "threshold must be greater than zero");
+        CircuitBreakerLayer {
+            settings: Settings {
+                threshold,
+                window,
+                cooldown,
+            },
+        }
+    }
+}
+
+impl<S> Layer<S> for CircuitBreakerLayer {
+    type Service = CircuitBreaker<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CircuitBreaker {
+            inner,
+            settings: self.settings,
+            state: Arc::new(Mutex::new(State {
+                throttles: VecDeque::new(),
+                open_until: None,
+            })),
+        }
+    }
+}
+
+impl<S> CircuitBreaker<S> {
+    pub fn new(inner: S, threshold: usize, window: Duration, cooldown: Duration) -> Self {
+        CircuitBreakerLayer::new(threshold, window, cooldown).layer(inner)
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> Service<SignedRequest> for CircuitBreaker<S>
+where
+    S: Service<SignedRequest, Response = HttpResponse, Error = HttpDispatchError>,
+{
+    type Response = HttpResponse;
+    type Error = HttpDispatchError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: SignedRequest) -> Self::Future {
+        let open_for = {
+            let mut state = self.state.lock().expect("lock poisoned");
+            let now = Instant::now();
+            match state.open_until {
+                Some(until) if until > now => Some(until - now),
+                Some(_) => {
+                    state.open_until = None;
+                    state.throttles.clear();
+                    None
+                }
+                None => None,
+            }
+        };
+
+        let (inner, error) = match open_for {
+            Some(open_for) => {
+                let msg = format!(
+                    "{} {}{}: circuit open after repeated throttling, retry in {:?}",
+                    request.method(),
+                    request.hostname(),
+                    request.canonical_path(),
+                    open_for
+                );
+                (None, Some(error::new(Kind::CircuitOpen, msg)))
+            }
+            None => (Some(self.inner.call(request)), None),
+        };
+
+        ResponseFuture {
+            inner,
+            error,
+            settings: self.settings,
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<F> Future for ResponseFuture<F>
+where
+    F: Future<Item = HttpResponse, Error = HttpDispatchError>,
+{
+    type Item = HttpResponse;
+    type Error = HttpDispatchError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = match &mut self.inner {
+            Some(inner) => inner,
+            None => return Err(self.error.take().expect("polled after completion")),
+        };
+
+        let response = match inner.poll() {
+            Ok(Async::Ready(response)) => response,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(e) => {
+                match Kind::of_dispatch_error(&e) {
+                    Some(Kind::ServerStatus) | Some(Kind::Status) => self.throttled(),
+                    _ => {}
+                }
+                return Err(e);
+            }
+        };
+        if is_throttle(&response) {
+            self.throttled();
+        }
+
+        Ok(Async::Ready(response))
+    }
+}
+
+impl<F> ResponseFuture<F> {
+    /// Count a throttled response, opening the circuit once there are enough
+    /// of them within the window.
+    fn throttled(&self) {
+        let mut state = self.state.lock().expect("lock poisoned");
+        let now = Instant::now();
+        state.throttles.push_back(now);
+        while let Some(&first) = state.throttles.front() {
+            if now.duration_since(first) <= self.settings.window {
+                break;
+            }
+            state.throttles.pop_front();
+        }
+
+        if state.throttles.len() >= self.settings.threshold {
+            state.open_until = Some(now + self.settings.cooldown);
+        }
+    }
+}
+
+fn is_throttle(response: &HttpResponse) -> bool {
+    if response.status == StatusCode::SERVICE_UNAVAILABLE
+        || response.status == StatusCode::TOO_MANY_REQUESTS
+    {
+        return true;
+    }
+
+    // The header looks like `ThrottlingException:http://internal.amazon.com/...`.
+    match response.headers.get("x-amzn-ErrorType") {
+        Some(error_type) => {
+            let error_type = error_type.split(':').next().unwrap_or("");
+            THROTTLE_ERROR_TYPES.contains(&error_type)
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock::{MockBody, MockService},
+        HttpClient,
+    };
+    use rusoto_core::Region;
+
+    fn unavailable() -> http::Response<MockBody> {
+        http::Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(MockBody::empty())
+            .unwrap()
+    }
+
+    fn call<S>(breaker: &mut CircuitBreaker<S>) -> Result<HttpResponse, HttpDispatchError>
+    where
+        S: Service<SignedRequest, Response = HttpResponse, Error = HttpDispatchError>,
+    {
+        let request = SignedRequest::new("GET", "s3", &Region::UsEast1, "/key");
+        breaker.call(request).wait()
+    }
+
+    #[test]
+    fn repeated_503s_trip_the_breaker() {
+        let mock = MockService::new();
+        for _ in 0..3 {
+            mock.respond_with(unavailable());
+        }
+        let client = HttpClient::new(mock.clone());
+        let mut breaker =
+            CircuitBreaker::new(client, 3, Duration::from_secs(60), Duration::from_secs(60));
+
+        for _ in 0..3 {
+            let response = call(&mut breaker).unwrap();
+            assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        let err = call(&mut breaker).unwrap_err().to_string();
+        assert!(err.starts_with("CircuitOpenError"), "{}", err);
+        assert_eq!(mock.take_requests().len(), 3);
+    }
+
+    #[test]
+    fn breaker_closes_after_the_cooldown() {
+        let mock = MockService::new();
+        mock.respond_with(unavailable());
+        let client = HttpClient::new(mock.clone());
+        let mut breaker = CircuitBreaker::new(
+            client,
+            1,
+            Duration::from_secs(60),
+            Duration::from_millis(10),
+        );
+
+        call(&mut breaker).unwrap();
+        assert!(call(&mut breaker).is_err());
+
+        std::thread::sleep(Duration::from_millis(20));
+        let response = call(&mut breaker).unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(mock.take_requests().len(), 2);
+    }
+
+    #[test]
+    fn status_errors_trip_the_breaker() {
+        let mock = MockService::new();
+        for _ in 0..2 {
+            mock.respond_with(unavailable());
+        }
+        let client = HttpClient::builder(mock.clone())
+            .treat_5xx_as_error(true)
+            .build();
+        let mut breaker =
+            CircuitBreaker::new(client, 2, Duration::from_secs(60), Duration::from_secs(60));
+
+        for _ in 0..2 {
+            let err = call(&mut breaker).unwrap_err();
+            assert_eq!(Kind::of_dispatch_error(&err), Some(Kind::ServerStatus));
+        }
+
+        let err = call(&mut breaker).unwrap_err();
+        assert_eq!(Kind::of_dispatch_error(&err), Some(Kind::CircuitOpen));
+        assert_eq!(mock.take_requests().len(), 2);
+    }
+}
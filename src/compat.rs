@@ -0,0 +1,50 @@
+//! `std::future` support, so dispatches can be awaited in an `async fn`.
+//!
+//! The futures are the futures 0.1 ones behind a compatibility shim, so they
+//! still have to run where a tokio 0.1 timer is available for timeouts.
+
+use crate::{future, HttpClient, RusotoBody};
+use futures03::compat::{Compat01As03, Future01CompatExt};
+use rusoto_core::{signature::SignedRequest, DispatchSignedRequest};
+use std::{error::Error, io, time::Duration};
+use tower_http::{Body, HttpService};
+
+/// `std::future::Future` returned by `HttpClient::dispatch_async`.
+pub type Dispatch<T> = Compat01As03<future::Dispatch<T>>;
+
+impl<T> HttpClient<T>
+where
+    T: HttpService<RusotoBody> + Clone + Send + 'static,
+    T::Future: Send + 'static,
+    T::ResponseBody: Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<io::Error>,
+    T::Error: Into<Box<dyn Error + Send + Sync>> + Send + 'static,
+{
+    /// Like `DispatchSignedRequest::dispatch`, returning a future that can be
+    /// `.await`ed.
+    pub fn dispatch_async(&self, request: SignedRequest, timeout: Option<Duration>) -> Dispatch<T> {
+        self.dispatch(request, timeout).compat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockService;
+    use http::StatusCode;
+    use rusoto_core::Region;
+
+    #[test]
+    fn dispatch_can_be_awaited() {
+        let mock = MockService::new();
+        let client = HttpClient::new(mock.clone());
+        let request = SignedRequest::new("GET", "s3", &Region::UsEast1, "/key");
+
+        let response =
+            futures03::executor::block_on(async { client.dispatch_async(request, None).await })
+                .unwrap();
+
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(mock.take_requests().len(), 1);
+    }
+}
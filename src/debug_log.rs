@@ -0,0 +1,189 @@
+use http::{
+    header::{HeaderMap, AUTHORIZATION, PROXY_AUTHORIZATION},
+    Request, Response, Uri,
+};
+use rusoto_core::signature::SignedRequest;
+use std::fmt;
+
+/// Headers whose values are never logged.
+const SENSITIVE: &[&str] = &["x-amz-security-token"];
+
+/// Query parameters of presigned URLs whose values are never logged.
+const SENSITIVE_QUERY: &[&str] = &[
+    "x-amz-signature",
+    "x-amz-credential",
+    "x-amz-security-token",
+];
+
+/// Log the headers of a signed request in the order rusoto holds them, which
+/// is the order they were canonicalized in for signing. Converting them into
+/// a `HeaderMap` doesn't keep it.
+pub(crate) fn signed(request: &SignedRequest) {
+    tracing::debug!(
+        method = %request.method(),
+        host = %request.hostname(),
+        path = %request.canonical_path(),
+        headers = %Signed(request),
+        "signed request",
+    );
+}
+
+/// Log the request as it is handed to the inner service.
+pub(crate) fn request<B>(request: &Request<B>) {
+    tracing::debug!(
+        method = %request.method(),
+        uri = %RedactedUri(request.uri()),
+        headers = %Redacted(request.headers()),
+        "sending request",
+    );
+}
+
+/// Log the head of a response as it arrives.
+pub(crate) fn response<B>(target: &impl fmt::Display, response: &Response<B>) {
+    tracing::debug!(
+        request = %target,
+        status = response.status().as_u16(),
+        headers = %Redacted(response.headers()),
+        "received response",
+    );
+}
+
+fn is_sensitive(name: &str) -> bool {
+    name.eq_ignore_ascii_case(AUTHORIZATION.as_str())
+        || name.eq_ignore_ascii_case(PROXY_AUTHORIZATION.as_str())
+        || SENSITIVE.iter().any(|s| name.eq_ignore_ascii_case(s))
+}
+
+/// Formats a URI with the values of sensitive query parameters replaced.
+struct RedactedUri<'a>(&'a Uri);
+
+impl fmt::Display for RedactedUri<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let (Some(scheme), Some(authority)) = (self.0.scheme_part(), self.0.authority_part()) {
+            write!(f, "{}://{}", scheme, authority)?;
+        }
+        f.write_str(self.0.path())?;
+
+        let query = match self.0.query() {
+            Some(query) => query,
+            None => return Ok(()),
+        };
+        for (i, pair) in query.split('&').enumerate() {
+            f.write_str(if i == 0 { "?" } else { "&" })?;
+            let name = pair.split('=').next().unwrap_or(pair);
+            if SENSITIVE_QUERY.iter().any(|s| name.eq_ignore_ascii_case(s)) {
+                write!(f, "{}=<redacted>", name)?;
+            } else {
+                f.write_str(pair)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Formats the headers of a signed request like `Redacted`.
+struct Signed<'a>(&'a SignedRequest);
+
+impl fmt::Display for Signed<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for (name, values) in self.0.headers() {
+            for value in values {
+                if !first {
+                    f.write_str(", ")?;
+                }
+                first = false;
+
+                if is_sensitive(name) {
+                    write!(f, "{}: <redacted>", name)?;
+                } else {
+                    write!(f, "{}: {}", name, String::from_utf8_lossy(value))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Formats headers as `name: value` pairs with sensitive values replaced.
+struct Redacted<'a>(&'a HeaderMap);
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for (name, value) in self.0 {
+            if !first {
+                f.write_str(", ")?;
+            }
+            first = false;
+
+            if is_sensitive(name.as_str()) {
+                write!(f, "{}: <redacted>", name)?;
+            } else {
+                write!(f, "{}: {}", name, String::from_utf8_lossy(value.as_bytes()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_sensitive_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            "AWS4-HMAC-SHA256 Signature=abc".parse().unwrap(),
+        );
+        headers.insert("x-amz-security-token", "tok".parse().unwrap());
+        headers.insert("x-amz-date", "20190101T000000Z".parse().unwrap());
+
+        let logged = Redacted(&headers).to_string();
+        assert!(logged.contains("authorization: <redacted>"), "{}", logged);
+        assert!(
+            logged.contains("x-amz-security-token: <redacted>"),
+            "{}",
+            logged
+        );
+        assert!(
+            logged.contains("x-amz-date: 20190101T000000Z"),
+            "{}",
+            logged
+        );
+        assert!(!logged.contains("Signature=abc"), "{}", logged);
+    }
+
+    #[test]
+    fn signed_headers_are_logged_in_signing_order() {
+        let mut request = SignedRequest::new("GET", "s3", &rusoto_core::Region::UsEast1, "/key");
+        request.add_header("x-amz-date", "20190101T000000Z");
+        request.add_header("host", "s3.amazonaws.com");
+        request.add_header("authorization", "AWS4-HMAC-SHA256 Signature=abc");
+        request.add_header("content-type", "text/plain");
+
+        assert_eq!(
+            Signed(&request).to_string(),
+            "authorization: <redacted>, content-type: text/plain, host: s3.amazonaws.com, \
+             x-amz-date: 20190101T000000Z"
+        );
+    }
+
+    #[test]
+    fn redacts_presigned_query_values() {
+        let uri: Uri = "https://bucket.s3.amazonaws.com/key?X-Amz-Algorithm=AWS4-HMAC-SHA256\
+                        &X-Amz-Credential=AKID%2F20190101&X-Amz-Signature=abc\
+                        &x-amz-security-token=tok"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            RedactedUri(&uri).to_string(),
+            "https://bucket.s3.amazonaws.com/key?X-Amz-Algorithm=AWS4-HMAC-SHA256\
+             &X-Amz-Credential=<redacted>&X-Amz-Signature=<redacted>\
+             &x-amz-security-token=<redacted>"
+        );
+    }
+}
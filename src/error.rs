@@ -0,0 +1,378 @@
+//! Categories a dispatch failure falls into.
+//!
+//! rusoto only gives us a message to work with, so the category is kept as
+//! the prefix of that message.
+
+use rusoto_core::request::HttpDispatchError;
+use std::{error::Error, fmt, io};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Kind {
+    /// The signed request could not be turned into an http request.
+    Build,
+    /// No connection could be established.
+    Connect,
+    /// The connection was closed before a response arrived.
+    Closed,
+    /// The request did not complete in time.
+    Timeout,
+    /// Any other failure of the inner service.
+    Dispatch,
+    /// The response could not be handed to rusoto, e.g. because of a header
+    /// that isn't valid UTF-8.
+    Mapping,
+    /// Reading the response body failed.
+    Body,
+    /// The response had a 5xx status that is treated as a failure.
+    ServerStatus,
+    /// The response had another status that is treated as a failure, e.g.
+    /// one a `status_policy` rejects.
+    Status,
+    /// A circuit breaker refused the request.
+    CircuitOpen,
+    /// The client is shutting down and takes no new requests.
+    Shutdown,
+    /// The timer driving a deadline failed.
+    Timer,
+}
+
+/// Wraps a response body error so its message carries the `BodyError` prefix.
+#[derive(Debug)]
+pub(crate) struct BodyError {
+    source: io::Error,
+    received: Option<usize>,
+}
+
+impl Kind {
+    /// Categorize an error returned by the inner service, by the first
+    /// `io::Error` in its chain of sources that says more than `Other`.
+    pub(crate) fn of(err: &(dyn Error + 'static)) -> Kind {
+        let kind = match io_kind(err) {
+            Some(kind) => kind,
+            None => return Kind::Dispatch,
+        };
+
+        match kind {
+            io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::AddrNotAvailable => Kind::Connect,
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::BrokenPipe => Kind::Closed,
+            io::ErrorKind::TimedOut => Kind::Timeout,
+            _ => Kind::Dispatch,
+        }
+    }
+
+    /// Recover the kind of a dispatch error from the prefix of its message.
+    ///
+    /// This only works for messages in the default format, not those of an
+    /// `error_formatter`.
+    pub(crate) fn of_dispatch_error(err: &HttpDispatchError) -> Option<Kind> {
+        const ALL: &[Kind] = &[
+            Kind::Build,
+            Kind::Connect,
+            Kind::Closed,
+            Kind::Timeout,
+            Kind::Dispatch,
+            Kind::Mapping,
+            Kind::Body,
+            Kind::ServerStatus,
+            Kind::Status,
+            Kind::CircuitOpen,
+            Kind::Shutdown,
+            Kind::Timer,
+        ];
+
+        let msg = err.to_string();
+        ALL.iter().cloned().find(|kind| {
+            msg.starts_with(kind.as_str()) && msg[kind.as_str().len()..].starts_with(':')
+        })
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Build => "RequestBuildingError",
+            Kind::Connect => "ConnectError",
+            Kind::Closed => "ConnectionClosedError",
+            Kind::Timeout => "TimeoutError",
+            Kind::Dispatch => "DispatchError",
+            Kind::Mapping => "ResponseMappingError",
+            Kind::Body => "BodyError",
+            Kind::ServerStatus => "ServerStatusError",
+            Kind::Status => "StatusError",
+            Kind::CircuitOpen => "CircuitOpenError",
+            Kind::Shutdown => "ShutdownError",
+            Kind::Timer => "TimerError",
+        }
+    }
+}
+
+/// The kind of the first `io::Error` in the chain of `err` and its sources
+/// that isn't `Other`.
+pub(crate) fn io_kind(err: &(dyn Error + 'static)) -> Option<io::ErrorKind> {
+    let mut next = Some(err);
+    while let Some(err) = next {
+        next = match err.downcast_ref::<io::Error>() {
+            Some(io) if io.kind() != io::ErrorKind::Other => return Some(io.kind()),
+            // `io::Error::source` skips the error it wraps, so step into it
+            // directly.
+            Some(io) => match io.get_ref() {
+                Some(inner) => Some(inner as &(dyn Error + 'static)),
+                None => None,
+            },
+            None => err.source(),
+        };
+    }
+    None
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What a `HttpDispatchError` is made from, handed to the formatter set with
+/// `HttpClientBuilder::error_formatter`.
+#[derive(Debug)]
+pub struct ErrorContext<'a> {
+    kind: Kind,
+    target: Option<&'a Target>,
+    message: &'a str,
+}
+
+/// The request a dispatch error is about.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Target {
+    method: String,
+    /// Without the query, which may carry a presigned signature.
+    uri: String,
+    request_id: Option<String>,
+}
+
+/// Produces the message of a `HttpDispatchError`.
+pub(crate) type Formatter = dyn Fn(&ErrorContext<'_>) -> String + Send + Sync;
+
+impl ErrorContext<'_> {
+    /// The category of the error, e.g. `TimeoutError`, which the default
+    /// format prefixes the message with.
+    pub fn kind(&self) -> &'static str {
+        self.kind.as_str()
+    }
+
+    /// The method of the request, if the error happened while sending it.
+    pub fn method(&self) -> Option<&str> {
+        self.target.map(|target| target.method.as_str())
+    }
+
+    /// The URI of the request without its query, if the error happened while
+    /// sending it.
+    pub fn uri(&self) -> Option<&str> {
+        self.target.map(|target| target.uri.as_str())
+    }
+
+    /// The `x-request-id` sent with the request, when
+    /// `HttpClientBuilder::request_id` is set.
+    pub fn request_id(&self) -> Option<&str> {
+        self.target
+            .and_then(|target| target.request_id.as_ref())
+            .map(String::as_str)
+    }
+
+    /// What went wrong. Unlike the default format this doesn't include the
+    /// method and URI, see `method` and `uri` for those.
+    pub fn message(&self) -> &str {
+        self.message
+    }
+}
+
+impl Target {
+    pub(crate) fn new(method: String, uri: String, request_id: Option<String>) -> Self {
+        Target {
+            method,
+            uri,
+            request_id,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.method, self.uri)?;
+        if let Some(id) = &self.request_id {
+            write!(f, " (request id {})", id)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn new<M: fmt::Display>(kind: Kind, msg: M) -> HttpDispatchError {
+    HttpDispatchError::new(format!("{}: {}", kind, msg))
+}
+
+/// Like `new`, formatting the message with `formatter` if there is one.
+pub(crate) fn format<M: fmt::Display>(
+    formatter: Option<&Formatter>,
+    kind: Kind,
+    msg: M,
+) -> HttpDispatchError {
+    format_at(formatter, kind, None, msg)
+}
+
+/// Like `format`, for an error about `target`, which the default format puts
+/// in front of the message.
+pub(crate) fn format_at<M: fmt::Display>(
+    formatter: Option<&Formatter>,
+    kind: Kind,
+    target: Option<&Target>,
+    msg: M,
+) -> HttpDispatchError {
+    match formatter {
+        Some(formatter) => {
+            let message = msg.to_string();
+            HttpDispatchError::new(formatter(&ErrorContext {
+                kind,
+                target,
+                message: &message,
+            }))
+        }
+        None => match target {
+            Some(target) => new(kind, format_args!("{}: {}", target, msg)),
+            None => new(kind, msg),
+        },
+    }
+}
+
+pub(crate) fn body(err: io::Error) -> io::Error {
+    io::Error::new(
+        err.kind(),
+        BodyError {
+            source: err,
+            received: None,
+        },
+    )
+}
+
+/// Like `body`, for an error of the inner body after `received` bytes of it
+/// were read, e.g. a connection reset partway through a download.
+pub(crate) fn body_interrupted(err: io::Error, received: usize) -> io::Error {
+    io::Error::new(
+        err.kind(),
+        BodyError {
+            source: err,
+            received: Some(received),
+        },
+    )
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.received {
+            Some(received) => write!(
+                f,
+                "{}: {} (after {} bytes of the body)",
+                Kind::Body,
+                self.source,
+                received
+            ),
+            None => write!(f, "{}: {}", Kind::Body, self.source),
+        }
+    }
+}
+
+impl Error for BodyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Wrapper(io::Error);
+
+    impl fmt::Display for Wrapper {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "wrapped: {}", self.0)
+        }
+    }
+
+    impl Error for Wrapper {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    fn target() -> Target {
+        Target::new(
+            "GET".to_string(),
+            "https://s3.amazonaws.com/bucket/key".to_string(),
+            Some("abc".to_string()),
+        )
+    }
+
+    #[test]
+    fn default_format_puts_the_target_first() {
+        let err = format_at(None, Kind::Timeout, Some(&target()), "request timed out");
+        assert_eq!(
+            err.to_string(),
+            "TimeoutError: GET https://s3.amazonaws.com/bucket/key (request id abc): \
+             request timed out"
+        );
+    }
+
+    #[test]
+    fn formatter_gets_the_target_separately() {
+        let formatter: Box<Formatter> = Box::new(|cx: &ErrorContext<'_>| {
+            format!(
+                "{}|{:?}|{:?}|{:?}|{}",
+                cx.kind(),
+                cx.method(),
+                cx.uri(),
+                cx.request_id(),
+                cx.message()
+            )
+        });
+        let err = format_at(Some(&*formatter), Kind::Timeout, Some(&target()), "late");
+        assert_eq!(
+            err.to_string(),
+            "TimeoutError|Some(\"GET\")|Some(\"https://s3.amazonaws.com/bucket/key\")\
+             |Some(\"abc\")|late"
+        );
+
+        let err = format(Some(&*formatter), Kind::Build, "bad header");
+        assert_eq!(
+            err.to_string(),
+            "RequestBuildingError|None|None|None|bad header"
+        );
+    }
+
+    #[test]
+    fn kind_of_top_level_io_error() {
+        let err = io::Error::from(io::ErrorKind::ConnectionRefused);
+        assert_eq!(Kind::of(&err), Kind::Connect);
+    }
+
+    #[test]
+    fn kind_of_walks_sources() {
+        let err = Wrapper(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert_eq!(Kind::of(&err), Kind::Closed);
+    }
+
+    #[test]
+    fn kind_of_looks_inside_other_io_errors() {
+        let inner = Wrapper(io::Error::from(io::ErrorKind::TimedOut));
+        let err = io::Error::new(io::ErrorKind::Other, inner);
+        assert_eq!(Kind::of(&err), Kind::Timeout);
+    }
+
+    #[test]
+    fn kind_of_unknown_error_is_dispatch() {
+        let err = io::Error::new(io::ErrorKind::Other, "boom");
+        assert_eq!(Kind::of(&err), Kind::Dispatch);
+    }
+}
@@ -0,0 +1,779 @@
+use crate::{
+    error::{Kind, Target},
+    limit::{Drain, InFlight, Permit},
+    BodyStream, Config, HeaderDecodePolicy, OnBodyTimeout, RusotoBody, X_REQUEST_ID,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{stream, Async, Future, Poll, Stream};
+use http::{
+    header::{HeaderMap, CONTENT_ENCODING, CONTENT_LENGTH, SET_COOKIE, TRANSFER_ENCODING},
+    response, Method, Request, Response, StatusCode,
+};
+use rusoto_core::{
+    request::{Headers, HttpDispatchError, HttpResponse},
+    signature::SignedRequestPayload,
+    ByteStream,
+};
+use std::{
+    borrow::Cow,
+    cmp,
+    error::Error,
+    io, mem,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio_timer::Delay;
+use tower_http::{Body, BodyExt, HttpService};
+
+/// The most `CollectBody` reserves before any data has arrived, so a bogus
+/// `Content-Length` can't make it allocate gigabytes up front.
+const MAX_COLLECT_RESERVE: usize = 4 * 1024 * 1024;
+
+/// Future returned by `HttpClient::dispatch`.
+///
+/// The request is only handed to the inner service once it reports that it
+/// is ready, so layers such as concurrency limits apply backpressure.
+///
+/// It resolves as soon as the response head arrives. Neither body is
+/// buffered, unless `response_trailers` is enabled, so over HTTP/2 the
+/// response can be read while the request body is still being sent.
+///
+/// Dropping it before it resolves drops the inner service's response future
+/// along with any request body it still owns, which cancels the request.
+pub struct Dispatch<T>
+where
+    T: HttpService<RusotoBody>,
+{
+    state: State<T>,
+    permit: Option<Permit>,
+    in_flight: Option<InFlight>,
+    started: Instant,
+    delay: Option<Delay>,
+    body_deadline: Option<Instant>,
+    head: bool,
+    target: Target,
+    config: Arc<Config>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "metrics")]
+    tag: Option<String>,
+}
+
+/// Future returned by `HttpClient::dispatch_http`.
+///
+/// It resolves to the response as the inner service returned it, with only
+/// the body turned into a `ByteStream`. The body is not decompressed and the
+/// response hooks that take an `HttpResponse` are not called.
+pub struct DispatchHttp<T>
+where
+    T: HttpService<RusotoBody>,
+{
+    inner: Dispatch<T>,
+}
+
+/// Future returned by `HttpClient::shutdown`.
+pub struct Shutdown {
+    drain: Arc<Drain>,
+}
+
+/// Future returned by `collect_body`.
+pub struct CollectBody {
+    body: ByteStream,
+    first: Option<Bytes>,
+    buf: BytesMut,
+    capacity: usize,
+}
+
+enum State<T>
+where
+    T: HttpService<RusotoBody>,
+{
+    NotReady {
+        service: T,
+        request: Option<Request<RusotoBody>>,
+    },
+    Pending(T::Future),
+    Collecting {
+        parts: response::Parts,
+        body: T::ResponseBody,
+        buf: BytesMut,
+        data_done: bool,
+    },
+    Failed(Option<HttpDispatchError>),
+}
+
+impl<T> Dispatch<T>
+where
+    T: HttpService<RusotoBody>,
+{
+    pub(crate) fn new(
+        service: T,
+        request: Request<RusotoBody>,
+        timeout: Option<Duration>,
+        config: Arc<Config>,
+        drain: &Arc<Drain>,
+    ) -> Self {
+        let now = Instant::now();
+        let body_deadline = config.deadlines.total.map(|total| now + total);
+        let delay = [timeout, config.deadlines.response_head]
+            .iter()
+            .filter_map(|d| d.map(|d| now + d))
+            .chain(body_deadline)
+            .min()
+            .map(Delay::new);
+        let head = request.method() == Method::HEAD;
+        let request_id = config
+            .request_id
+            .as_ref()
+            .and_then(|_| request.headers().get(X_REQUEST_ID))
+            .map(|id| String::from_utf8_lossy(id.as_bytes()).into_owned());
+        // The query is left out, presigned URLs carry their signature in it.
+        let target = Target::new(
+            request.method().to_string(),
+            crate::without_query(request.uri()),
+            request_id,
+        );
+
+        let in_flight = drain.enter();
+        let state = if in_flight.is_some() {
+            State::NotReady {
+                service,
+                request: Some(request),
+            }
+        } else {
+            let msg = "the client is shutting down";
+            State::Failed(Some(config.error_at(Kind::Shutdown, &target, msg)))
+        };
+
+        Dispatch {
+            state,
+            permit: None,
+            in_flight,
+            started: now,
+            delay,
+            body_deadline,
+            head,
+            target,
+            config,
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::none(),
+            #[cfg(feature = "metrics")]
+            tag: None,
+        }
+    }
+
+    pub(crate) fn error(err: HttpDispatchError, config: Arc<Config>) -> Self {
+        Dispatch {
+            state: State::Failed(Some(err)),
+            permit: None,
+            in_flight: None,
+            started: Instant::now(),
+            delay: None,
+            body_deadline: None,
+            head: false,
+            target: Target::default(),
+            config,
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::none(),
+            #[cfg(feature = "metrics")]
+            tag: None,
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    pub(crate) fn with_span(mut self, span: tracing::Span) -> Self {
+        self.span = span;
+        self
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn with_tag(mut self, tag: Option<String>) -> Self {
+        self.tag = tag;
+        self
+    }
+}
+
+impl<T> Future for Dispatch<T>
+where
+    T: HttpService<RusotoBody>,
+    T::Error: Into<Box<dyn Error + Send + Sync>>,
+    T::ResponseBody: Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<io::Error>,
+{
+    type Item = HttpResponse;
+    type Error = HttpDispatchError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        #[cfg(feature = "tracing")]
+        let _enter = self.span.enter();
+
+        let result = self.poll_response();
+        // Hand the permit back as soon as the dispatch is done with.
+        if !matches_not_ready(&result) {
+            drop(self.permit.take());
+            drop(self.in_flight.take());
+        }
+
+        if let Some(on_complete) = &self.config.on_complete {
+            match &result {
+                Ok(Async::Ready(response)) => on_complete(self.started.elapsed(), Ok(response)),
+                Ok(Async::NotReady) => {}
+                Err(err) => on_complete(self.started.elapsed(), Err(err)),
+            }
+        }
+
+        match &result {
+            Ok(Async::Ready(response)) => self.record(Ok(response.status)),
+            Ok(Async::NotReady) => {}
+            Err(err) => self.record(Err(err)),
+        }
+
+        result
+    }
+}
+
+impl<T> Dispatch<T>
+where
+    T: HttpService<RusotoBody>,
+{
+    /// Report the outcome of a resolved dispatch to tracing and metrics.
+    #[allow(unused_variables)]
+    fn record(&self, outcome: Result<StatusCode, &HttpDispatchError>) {
+        #[cfg(feature = "tracing")]
+        crate::trace::record(&self.span, outcome);
+
+        #[cfg(feature = "metrics")]
+        {
+            if let Some(metrics) = &self.config.metrics {
+                let tag = self.tag.as_ref().map(String::as_str);
+                crate::metrics::record(&**metrics, outcome, tag);
+            }
+        }
+    }
+
+    /// What a response body that outlives the total deadline reports to.
+    fn on_body_timeout(&self) -> Option<OnBodyTimeout> {
+        #[cfg(feature = "metrics")]
+        {
+            if let Some(metrics) = &self.config.metrics {
+                let (metrics, tag) = (metrics.clone(), self.tag.clone());
+                return Some(Box::new(move || {
+                    metrics.timed_out(tag.as_ref().map(String::as_str))
+                }));
+            }
+        }
+
+        None
+    }
+}
+
+impl<T> Dispatch<T>
+where
+    T: HttpService<RusotoBody>,
+    T::Error: Into<Box<dyn Error + Send + Sync>>,
+    T::ResponseBody: Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<io::Error>,
+{
+    fn poll_response(&mut self) -> Poll<HttpResponse, HttpDispatchError> {
+        let collecting = match self.state {
+            State::Collecting { .. } => true,
+            _ => false,
+        };
+
+        if !collecting {
+            let response = futures::try_ready!(self.poll_head());
+            if !self.config.response_trailers {
+                let on_timeout = self.on_body_timeout();
+                return map_response(
+                    response,
+                    &self.config,
+                    self.body_deadline,
+                    on_timeout,
+                    self.head,
+                )
+                .map(Async::Ready)
+                .map_err(|e| self.config.error_at(Kind::Mapping, &self.target, e));
+            }
+
+            let (parts, body) = response.into_parts();
+            // The timeout and the response head deadline are done with once
+            // the head is in, only the total deadline bounds the body.
+            self.delay = self.body_deadline.map(Delay::new);
+            self.state = State::Collecting {
+                parts,
+                body,
+                buf: BytesMut::new(),
+                data_done: false,
+            };
+        }
+
+        self.poll_collect()
+    }
+
+    /// Poll until the response head arrives.
+    fn poll_head(&mut self) -> Poll<Response<T::ResponseBody>, HttpDispatchError> {
+        loop {
+            match &mut self.state {
+                State::NotReady { service, request } => {
+                    if let (Some(limit), None) = (&self.config.limit, &self.permit) {
+                        match limit.poll_acquire() {
+                            Async::Ready(permit) => self.permit = Some(permit),
+                            Async::NotReady => break,
+                        }
+                    }
+
+                    match service.poll_ready() {
+                        Ok(Async::Ready(())) => {
+                            let request = request.take().expect("polled after completion");
+                            if let Some(shadow) = &self.config.shadow {
+                                if let Some(copy) = crate::shadow::try_clone(&request) {
+                                    shadow(copy);
+                                }
+                            }
+                            #[cfg(feature = "debug-log")]
+                            crate::debug_log::request(&request);
+                            self.state = State::Pending(service.call(request));
+                        }
+                        Ok(Async::NotReady) => break,
+                        Err(e) => {
+                            let err: Box<dyn Error + Send + Sync> = e.into();
+                            return Err(self.config.error_at(Kind::of(&*err), &self.target, err));
+                        }
+                    }
+                }
+                State::Pending(future) => match future.poll() {
+                    Ok(Async::Ready(response)) => {
+                        #[cfg(feature = "debug-log")]
+                        crate::debug_log::response(&self.target, &response);
+
+                        let status = response.status();
+                        if self.config.is_error_status(status) {
+                            // A 5xx gets a kind of its own, so a retry can tell
+                            // it apart without reading the message.
+                            let kind = if status.is_server_error() {
+                                Kind::ServerStatus
+                            } else {
+                                Kind::Status
+                            };
+                            let msg = format!("server responded with {}", status);
+                            return Err(self.config.error_at(kind, &self.target, msg));
+                        }
+
+                        return Ok(Async::Ready(response));
+                    }
+                    Ok(Async::NotReady) => break,
+                    Err(e) => {
+                        let err: Box<dyn Error + Send + Sync> = e.into();
+                        let kind = Kind::of(&*err);
+                        let msg = if kind == Kind::Closed {
+                            format!("connection closed before response: {}", err)
+                        } else {
+                            err.to_string()
+                        };
+                        return Err(self.config.error_at(kind, &self.target, msg));
+                    }
+                },
+                State::Collecting { .. } => unreachable!("response head already received"),
+                State::Failed(err) => return Err(err.take().expect("polled after completion")),
+            }
+        }
+
+        self.poll_delay()?;
+        Ok(Async::NotReady)
+    }
+
+    /// Poll until the whole response body and its trailers have been read.
+    fn poll_collect(&mut self) -> Poll<HttpResponse, HttpDispatchError> {
+        let trailers = match &mut self.state {
+            State::Collecting {
+                body,
+                buf,
+                data_done,
+                ..
+            } => {
+                while !*data_done {
+                    match body.poll_buf() {
+                        Ok(Async::Ready(Some(chunk))) => {
+                            buf.reserve(chunk.remaining());
+                            buf.put(chunk);
+                            if let Some(max) = self.config.max_response_bytes {
+                                if buf.len() > max {
+                                    let msg = format!(
+                                        "response body exceeded the limit of {} bytes",
+                                        max
+                                    );
+                                    return Err(self.config.error_at(
+                                        Kind::Body,
+                                        &self.target,
+                                        msg,
+                                    ));
+                                }
+                            }
+                        }
+                        Ok(Async::Ready(None)) => *data_done = true,
+                        Ok(Async::NotReady) => {
+                            self.poll_delay()?;
+                            return Ok(Async::NotReady);
+                        }
+                        Err(e) => {
+                            let err: io::Error = e.into();
+                            return Err(self.config.error_at(Kind::Body, &self.target, err));
+                        }
+                    }
+                }
+
+                match body.poll_trailers() {
+                    Ok(Async::Ready(trailers)) => trailers.unwrap_or_default(),
+                    Ok(Async::NotReady) => {
+                        self.poll_delay()?;
+                        return Ok(Async::NotReady);
+                    }
+                    Err(e) => {
+                        let err: io::Error = e.into();
+                        return Err(self.config.error_at(Kind::Body, &self.target, err));
+                    }
+                }
+            }
+            _ => unreachable!("not collecting the response body"),
+        };
+
+        let state = mem::replace(&mut self.state, State::Failed(None));
+        let (mut parts, buf) = match state {
+            State::Collecting { parts, buf, .. } => (parts, buf),
+            _ => unreachable!(),
+        };
+        for name in trailers.keys() {
+            for value in trailers.get_all(name) {
+                parts.headers.append(name, value.clone());
+            }
+        }
+
+        let body = RusotoBody::from(Some(SignedRequestPayload::Buffer(buf.freeze())));
+        let response = Response::from_parts(parts, body);
+        map_response(response, &self.config, None, None, self.head)
+            .map(Async::Ready)
+            .map_err(|e| self.config.error_at(Kind::Mapping, &self.target, e))
+    }
+
+    /// Fail once the deadline of the dispatch has passed.
+    fn poll_delay(&mut self) -> Result<(), HttpDispatchError> {
+        let delay = match &mut self.delay {
+            Some(delay) => delay,
+            None => return Ok(()),
+        };
+
+        match delay.poll() {
+            Ok(Async::Ready(())) => {
+                #[cfg(feature = "metrics")]
+                {
+                    if let Some(metrics) = &self.config.metrics {
+                        metrics.timed_out(self.tag.as_ref().map(String::as_str));
+                    }
+                }
+
+                Err(self
+                    .config
+                    .error_at(Kind::Timeout, &self.target, "request timed out"))
+            }
+            Ok(Async::NotReady) => Ok(()),
+            Err(e) => Err(self.config.error_at(Kind::Timer, &self.target, e)),
+        }
+    }
+}
+
+impl<T> DispatchHttp<T>
+where
+    T: HttpService<RusotoBody>,
+{
+    pub(crate) fn new(inner: Dispatch<T>) -> Self {
+        DispatchHttp { inner }
+    }
+}
+
+impl<T> Future for DispatchHttp<T>
+where
+    T: HttpService<RusotoBody>,
+    T::Error: Into<Box<dyn Error + Send + Sync>>,
+    T::ResponseBody: Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<io::Error>,
+{
+    type Item = Response<ByteStream>;
+    type Error = HttpDispatchError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        #[cfg(feature = "tracing")]
+        let _enter = self.inner.span.enter();
+
+        let result = self.inner.poll_head();
+        if !matches_not_ready(&result) {
+            drop(self.inner.permit.take());
+            drop(self.inner.in_flight.take());
+        }
+
+        match &result {
+            Ok(Async::Ready(response)) => self.inner.record(Ok(response.status())),
+            Ok(Async::NotReady) => {}
+            Err(err) => {
+                // There is no rusoto `HttpResponse` to hand `on_complete` here,
+                // so it only hears about failures.
+                if let Some(on_complete) = &self.inner.config.on_complete {
+                    on_complete(self.inner.started.elapsed(), Err(err));
+                }
+                self.inner.record(Err(err));
+            }
+        }
+
+        let response = futures::try_ready!(result);
+        let config = &self.inner.config;
+        let deadline = self.inner.body_deadline;
+        let on_timeout = self.inner.on_body_timeout();
+        Ok(Async::Ready(response.map(|body| {
+            let body = BodyStream::new(
+                body.into_buf_stream(),
+                config.max_response_bytes,
+                deadline.map(Delay::new),
+                config.download_progress.clone(),
+                on_timeout,
+            );
+            ByteStream::new(body)
+        })))
+    }
+}
+
+impl Shutdown {
+    pub(crate) fn new(drain: Arc<Drain>) -> Self {
+        Shutdown { drain }
+    }
+}
+
+impl Future for Shutdown {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        Ok(self.drain.poll_drained())
+    }
+}
+
+impl CollectBody {
+    /// Collect the body of `response`, reserving no more than `max_bytes` up
+    /// front.
+    pub(crate) fn new(response: HttpResponse, max_bytes: Option<usize>) -> Self {
+        let declared = response
+            .body
+            .size_hint()
+            .or_else(|| {
+                response
+                    .headers
+                    .get("content-length")
+                    .and_then(|v| v.parse().ok())
+            })
+            .unwrap_or(0);
+        let capacity = cmp::min(
+            cmp::min(declared, MAX_COLLECT_RESERVE),
+            max_bytes.unwrap_or(usize::max_value()),
+        );
+
+        CollectBody {
+            body: response.body,
+            first: None,
+            buf: BytesMut::new(),
+            capacity,
+        }
+    }
+}
+
+impl Future for CollectBody {
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        while let Some(chunk) = futures::try_ready!(self.body.poll()) {
+            // A body that arrives in one chunk is handed back without a copy.
+            if self.first.is_none() && self.buf.is_empty() {
+                self.first = Some(chunk);
+                continue;
+            }
+
+            if let Some(first) = self.first.take() {
+                self.buf
+                    .reserve(cmp::max(self.capacity, first.len() + chunk.len()));
+                self.buf.put_slice(&first);
+            }
+            self.buf.reserve(chunk.len());
+            self.buf.put_slice(&chunk);
+        }
+
+        match self.first.take() {
+            Some(first) => Ok(Async::Ready(first)),
+            None => Ok(Async::Ready(
+                mem::replace(&mut self.buf, BytesMut::new()).freeze(),
+            )),
+        }
+    }
+}
+
+fn matches_not_ready<T, E>(result: &Poll<T, E>) -> bool {
+    match result {
+        Ok(Async::NotReady) => true,
+        _ => false,
+    }
+}
+
+fn map_response<B>(
+    response: Response<B>,
+    config: &Config,
+    body_deadline: Option<Instant>,
+    on_timeout: Option<OnBodyTimeout>,
+    head: bool,
+) -> Result<HttpResponse, String>
+where
+    B: Body + Send + 'static,
+    B::Error: Into<io::Error>,
+{
+    // The `Content-Length` of a response to `HEAD` is that of the body a
+    // `GET` would have gotten, none is actually sent. It is kept, e.g. for
+    // the object size of S3's `HeadObject`, as there is nothing to gunzip.
+    if head {
+        return Ok(HttpResponse {
+            status: response.status(),
+            headers: map_headers(response.headers(), false, config)?,
+            body: ByteStream::new_with_size(stream::empty(), 0),
+        });
+    }
+
+    let gunzip = config.gunzip(response.headers());
+
+    let status = response.status();
+    // A `Transfer-Encoding` takes precedence over any `Content-Length`, so a
+    // chunked response never gets a size hint.
+    let content_length = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .filter(|_| !response.headers().contains_key(TRANSFER_ENCODING))
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    let headers = map_headers(response.headers(), gunzip, config)?;
+    let body = response.into_body().into_buf_stream();
+    let body = BodyStream::new(
+        body,
+        config.max_response_bytes,
+        body_deadline.map(Delay::new),
+        config.download_progress.clone(),
+        on_timeout,
+    );
+
+    #[cfg(feature = "gzip")]
+    {
+        if gunzip {
+            return Ok(HttpResponse {
+                status,
+                headers,
+                body: ByteStream::new(crate::gzip::Gunzip::new(body, config.max_response_bytes)),
+            });
+        }
+    }
+
+    let body = match content_length {
+        Some(len) => ByteStream::new_with_size(body, len),
+        None => ByteStream::new(body),
+    };
+
+    Ok(HttpResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+fn map_headers(headers: &HeaderMap, gunzip: bool, config: &Config) -> Result<Headers, String> {
+    let mut mapped = Vec::new();
+    for name in headers.keys() {
+        if gunzip && (name == CONTENT_LENGTH || name == CONTENT_ENCODING) {
+            continue;
+        }
+        if let Some(filter) = &config.response_header_filter {
+            if !filter(name) {
+                continue;
+            }
+        }
+
+        let mut values = Vec::new();
+        for value in headers.get_all(name) {
+            match value.to_str() {
+                Ok(value) => values.push(Cow::Borrowed(value)),
+                Err(_) => match config.header_decode {
+                    HeaderDecodePolicy::Lossy => {
+                        values.push(String::from_utf8_lossy(value.as_bytes()))
+                    }
+                    HeaderDecodePolicy::Skip => {}
+                    HeaderDecodePolicy::Error => {
+                        return Err(format!("response header {} is not valid UTF-8", name));
+                    }
+                },
+            }
+        }
+
+        // rusoto keeps a single value per header name, so repeated headers are
+        // folded into one comma separated value rather than overwriting each other.
+        // `Set-Cookie` can't be folded that way since cookie dates contain
+        // commas (RFC 7230 section 3.2.2), so its values are separated by
+        // newlines, which can't appear in a header value.
+        if !values.is_empty() {
+            let separator = if name == SET_COOKIE { "\n" } else { ", " };
+            mapped.push((name.as_str(), values.join(separator)));
+        }
+    }
+
+    Ok(Headers::new(mapped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::header::HeaderValue;
+
+    #[test]
+    fn repeated_headers_are_folded() {
+        let mut headers = HeaderMap::new();
+        headers.append("x-amz-meta-tag", HeaderValue::from_static("a"));
+        headers.append("x-amz-meta-tag", HeaderValue::from_static("b"));
+
+        let mapped = map_headers(&headers, false, &Config::default()).unwrap();
+        assert_eq!(mapped.get("x-amz-meta-tag"), Some("a, b"));
+    }
+
+    #[test]
+    fn set_cookie_values_are_kept_apart() {
+        let first = "a=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT";
+        let second = "b=2; Expires=Thu, 22 Oct 2015 07:28:00 GMT";
+        let mut headers = HeaderMap::new();
+        headers.append(SET_COOKIE, HeaderValue::from_static(first));
+        headers.append(SET_COOKIE, HeaderValue::from_static(second));
+
+        let mapped = map_headers(&headers, false, &Config::default()).unwrap();
+        let cookies = mapped.get("set-cookie").unwrap();
+        assert_eq!(cookies.split('\n').collect::<Vec<_>>(), vec![first, second]);
+    }
+
+    #[test]
+    fn collect_body_caps_the_up_front_reserve() {
+        let response = |declared| {
+            let chunks = stream::iter_ok::<_, io::Error>(vec![Bytes::from("abc")]);
+            HttpResponse {
+                status: StatusCode::OK,
+                headers: Headers::new(Vec::new()),
+                body: ByteStream::new_with_size(chunks, declared),
+            }
+        };
+
+        let collect = CollectBody::new(response(usize::max_value()), None);
+        assert_eq!(collect.capacity, MAX_COLLECT_RESERVE);
+        let collect = CollectBody::new(response(usize::max_value()), Some(1024));
+        assert_eq!(collect.capacity, 1024);
+        let collect = CollectBody::new(response(3), Some(1024));
+        assert_eq!(collect.capacity, 3);
+
+        assert_eq!(collect.wait().unwrap(), Bytes::from("abc"));
+    }
+}
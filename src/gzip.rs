@@ -0,0 +1,134 @@
+use bytes::Bytes;
+use flate2::write::GzDecoder;
+use futures::{try_ready, Async, Poll, Stream};
+use std::{
+    io::{self, Write},
+    mem,
+};
+
+/// Compressed input is fed to the decoder in pieces of this size, so a body
+/// that inflates far beyond its limit is caught before it is all in memory.
+const INPUT_PIECE: usize = 4 * 1024;
+
+/// Streaming gunzip of a response body.
+pub(crate) struct Gunzip<S> {
+    inner: S,
+    decoder: Option<GzDecoder<Vec<u8>>>,
+    max_bytes: Option<usize>,
+    decoded: usize,
+}
+
+impl<S> Gunzip<S> {
+    /// Gunzip `inner`, failing once more than `max_bytes` have been decoded.
+    pub(crate) fn new(inner: S, max_bytes: Option<usize>) -> Self {
+        Gunzip {
+            inner,
+            decoder: Some(GzDecoder::new(Vec::new())),
+            max_bytes,
+            decoded: 0,
+        }
+    }
+
+    fn check_limit(&self, pending: usize) -> io::Result<()> {
+        match self.max_bytes {
+            Some(max) if self.decoded + pending > max => {
+                let msg = format!(
+                    "decompressed response body exceeded the limit of {} bytes",
+                    max
+                );
+                Err(crate::error::body(io::Error::new(
+                    io::ErrorKind::Other,
+                    msg,
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn take_decoded(&mut self, decoded: Vec<u8>) -> Poll<Option<Bytes>, io::Error> {
+        self.decoded += decoded.len();
+        Ok(Async::Ready(Some(Bytes::from(decoded))))
+    }
+}
+
+impl<S> Stream for Gunzip<S>
+where
+    S: Stream<Item = Bytes, Error = io::Error>,
+{
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if self.decoder.is_none() {
+                return Ok(Async::Ready(None));
+            }
+
+            match try_ready!(self.inner.poll()) {
+                Some(chunk) => {
+                    for piece in chunk.chunks(INPUT_PIECE) {
+                        let decoder = self.decoder.as_mut().expect("decoder");
+                        decoder.write_all(piece)?;
+                        let pending = decoder.get_ref().len();
+                        self.check_limit(pending)?;
+                    }
+                    let decoder = self.decoder.as_mut().expect("decoder");
+                    let decoded = mem::replace(decoder.get_mut(), Vec::new());
+                    if !decoded.is_empty() {
+                        return self.take_decoded(decoded);
+                    }
+                }
+                None => {
+                    let decoded = self.decoder.take().expect("decoder").finish()?;
+                    self.check_limit(decoded.len())?;
+                    if decoded.is_empty() {
+                        return Ok(Async::Ready(None));
+                    }
+                    return self.take_decoded(decoded);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use futures::stream;
+
+    fn gzip(data: &[u8]) -> Bytes {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(data).unwrap();
+        Bytes::from(encoder.finish().unwrap())
+    }
+
+    fn gunzip(compressed: Bytes, max_bytes: Option<usize>) -> io::Result<Vec<u8>> {
+        let inner = stream::iter_ok::<_, io::Error>(vec![compressed]);
+        Gunzip::new(inner, max_bytes)
+            .wait()
+            .try_fold(Vec::new(), |mut out, chunk| {
+                out.extend_from_slice(&chunk?);
+                Ok(out)
+            })
+    }
+
+    #[test]
+    fn decodes_within_limit() {
+        let data = b"hello world".repeat(100);
+        let decoded = gunzip(gzip(&data), Some(data.len())).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn limit_applies_to_decompressed_bytes() {
+        let data = vec![0; 16 * 1024 * 1024];
+        let compressed = gzip(&data);
+        assert!(compressed.len() < 64 * 1024);
+
+        let err = gunzip(compressed, Some(64 * 1024)).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("exceeded the limit of 65536 bytes"));
+    }
+}
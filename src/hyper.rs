@@ -0,0 +1,844 @@
+//! An `HttpService` backed by a hyper connection pool.
+
+use crate::{HttpClient, RusotoBody};
+use futures::{Async, Future, Poll};
+use http::{HeaderMap, Method, Request, Response, Uri};
+use hyper::{
+    body::Payload,
+    client::{
+        connect::{
+            dns::{GaiResolver, Name, Resolve},
+            Connect,
+        },
+        HttpConnector,
+    },
+    Chunk, Client,
+};
+use std::{
+    collections::HashMap, error::Error, fmt, io, net::IpAddr, sync::Arc, time::Duration, vec,
+};
+use tower_http::Body;
+use tower_service::Service;
+
+const DNS_THREADS: usize = 4;
+
+/// The connector used for HTTPS: hyper-tls with the `native-tls` feature,
+/// hyper-rustls when only the `rustls` feature is enabled.
+#[cfg(feature = "native-tls")]
+pub type HttpsConnector = hyper_tls::HttpsConnector<HttpConnector>;
+#[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+pub type HttpsConnector = hyper_rustls::HttpsConnector<HttpConnector>;
+
+/// Wraps a hyper `Client` so that it can be used as the inner service of an
+/// `HttpClient`.
+#[derive(Clone, Debug)]
+pub struct HyperService<C> {
+    client: Client<C, RusotoBody>,
+}
+
+/// Configures the hyper client behind a `HyperService`.
+#[derive(Clone, Debug)]
+pub struct Builder {
+    pool_idle_timeout: Option<Duration>,
+    pool_max_idle_per_host: usize,
+    http2_prior_knowledge: bool,
+    nodelay: bool,
+    tcp_keepalive: Option<Duration>,
+    connect_timeout: Option<Duration>,
+}
+
+/// An HTTP proxy that requests are tunneled through with `CONNECT`.
+#[cfg(feature = "proxy")]
+#[derive(Clone, Debug)]
+pub struct Proxy {
+    uri: Uri,
+    credentials: Option<(String, String)>,
+}
+
+/// Resolves pinned hosts to fixed addresses, and every other host through
+/// the system resolver.
+///
+/// Only the address connected to changes, the `Host` header and TLS SNI keep
+/// the original hostname. Hostnames are matched ignoring ASCII case.
+#[derive(Clone)]
+pub struct StaticResolver {
+    hosts: Arc<HashMap<String, Vec<IpAddr>>>,
+    fallback: GaiResolver,
+}
+
+/// Response future of a `HyperService`.
+pub struct ResponseFuture {
+    inner: hyper::client::ResponseFuture,
+}
+
+/// Future returned by `HyperService::warmup`.
+pub struct Warmup {
+    inner: hyper::client::ResponseFuture,
+}
+
+/// Response body of a `HyperService`.
+pub struct ResponseBody {
+    inner: hyper::Body,
+}
+
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+impl HttpClient<HyperService<HttpsConnector>> {
+    /// Create a client that speaks both HTTP and HTTPS over a pooled hyper
+    /// client with the default `Builder` settings.
+    pub fn https() -> io::Result<Self> {
+        Builder::new().https()
+    }
+}
+
+impl HttpClient<HyperService<HttpConnector>> {
+    /// Create a client that only speaks plain HTTP over a pooled hyper client
+    /// with the default `Builder` settings.
+    pub fn http() -> Self {
+        Builder::new().http()
+    }
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Builder {
+            pool_idle_timeout: Some(Duration::from_secs(90)),
+            pool_max_idle_per_host: usize::max_value(),
+            http2_prior_knowledge: false,
+            nodelay: false,
+            tcp_keepalive: None,
+            connect_timeout: None,
+        }
+    }
+
+    /// Set how long an idle pooled connection is kept around, `None` keeps
+    /// them forever.
+    ///
+    /// S3 closes idle connections after roughly 20 seconds, so setting this
+    /// below that avoids reusing connections the server already reset.
+    ///
+    /// hyper 0.12 can't send HTTP/2 keepalive pings, so there is no interval
+    /// for them here; `tcp_keepalive` keeps idle connections from being
+    /// dropped by firewalls and NATs along the way instead.
+    pub fn pool_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.pool_idle_timeout = timeout;
+        self
+    }
+
+    /// Set the maximum number of idle connections kept per host.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = max;
+        self
+    }
+
+    /// Speak HTTP/2 on every connection without negotiating it first.
+    ///
+    /// Only use this for endpoints known to accept HTTP/2 directly, plain
+    /// HTTP/1 servers will reject the connection preface.
+    pub fn http2_prior_knowledge(mut self, enabled: bool) -> Self {
+        self.http2_prior_knowledge = enabled;
+        self
+    }
+
+    /// Set `TCP_NODELAY` on new connections, which avoids Nagle's algorithm
+    /// holding back small requests. Defaults to `false`.
+    pub fn nodelay(mut self, enabled: bool) -> Self {
+        self.nodelay = enabled;
+        self
+    }
+
+    /// Enable TCP keepalive on new connections with the given idle time,
+    /// `None` leaves it off. Defaults to `None`.
+    pub fn tcp_keepalive(mut self, keepalive: Option<Duration>) -> Self {
+        self.tcp_keepalive = keepalive;
+        self
+    }
+
+    /// Fail connecting to a host that takes longer than `timeout`, `None`
+    /// waits for the OS to give up. Defaults to `None`.
+    ///
+    /// This bounds the TCP connect only, not the TLS handshake.
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    pub fn https(&self) -> io::Result<HttpClient<HyperService<HttpsConnector>>> {
+        self.https_service().map(HttpClient::new)
+    }
+
+    pub fn http(&self) -> HttpClient<HyperService<HttpConnector>> {
+        HttpClient::new(self.http_service())
+    }
+
+    /// Build the `HyperService` alone, e.g. to pass to `HttpClient::builder`.
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    pub fn https_service(&self) -> io::Result<HyperService<HttpsConnector>> {
+        #[cfg(feature = "native-tls")]
+        let connector = {
+            let mut http = self.http_connector(HttpConnector::new(DNS_THREADS));
+            http.enforce_http(false);
+            let tls = native_tls_crate::TlsConnector::new()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            hyper_tls::HttpsConnector::from((http, tls))
+        };
+        // The same roots `hyper_rustls::HttpsConnector::new` trusts, but over
+        // an `HttpConnector` with the socket options applied.
+        #[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+        let connector = {
+            let mut http = self.http_connector(HttpConnector::new(DNS_THREADS));
+            http.enforce_http(false);
+            let mut tls = rustls_crate::ClientConfig::new();
+            tls.root_store
+                .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+            hyper_rustls::HttpsConnector::from((http, tls))
+        };
+
+        Ok(self.service(connector))
+    }
+
+    pub fn http_service(&self) -> HyperService<HttpConnector> {
+        self.service(self.http_connector(HttpConnector::new(DNS_THREADS)))
+    }
+
+    /// Like `https_service`, but resolve hostnames with `resolver`, e.g. a
+    /// `StaticResolver` that pins endpoints to known addresses.
+    #[cfg(feature = "native-tls")]
+    pub fn https_service_with_resolver<R>(
+        &self,
+        resolver: R,
+    ) -> io::Result<HyperService<hyper_tls::HttpsConnector<HttpConnector<R>>>>
+    where
+        R: Resolve + Clone + Send + Sync + 'static,
+        R::Future: Send,
+    {
+        let mut http = self.http_connector(HttpConnector::new_with_resolver(resolver));
+        http.enforce_http(false);
+        let tls = native_tls_crate::TlsConnector::new()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        Ok(self.service(hyper_tls::HttpsConnector::from((http, tls))))
+    }
+
+    /// Like `https_service`, but resolve hostnames with `resolver`, e.g. a
+    /// `StaticResolver` that pins endpoints to known addresses.
+    #[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+    pub fn https_service_with_resolver<R>(
+        &self,
+        resolver: R,
+    ) -> io::Result<HyperService<hyper_rustls::HttpsConnector<HttpConnector<R>>>>
+    where
+        R: Resolve + Clone + Send + Sync + 'static,
+        R::Future: Send,
+    {
+        let mut http = self.http_connector(HttpConnector::new_with_resolver(resolver));
+        http.enforce_http(false);
+        let mut tls = rustls_crate::ClientConfig::new();
+        tls.root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+
+        Ok(self.service(hyper_rustls::HttpsConnector::from((http, tls))))
+    }
+
+    /// Like `http_service`, but resolve hostnames with `resolver`.
+    pub fn http_service_with_resolver<R>(&self, resolver: R) -> HyperService<HttpConnector<R>>
+    where
+        R: Resolve + Clone + Send + Sync + 'static,
+        R::Future: Send,
+    {
+        self.service(self.http_connector(HttpConnector::new_with_resolver(resolver)))
+    }
+
+    /// Create a client that reaches every host through `proxy`.
+    #[cfg(feature = "proxy")]
+    pub fn proxy(
+        &self,
+        proxy: Proxy,
+    ) -> io::Result<HttpClient<HyperService<hyper_proxy::ProxyConnector<HttpConnector>>>> {
+        self.proxy_service(proxy).map(HttpClient::new)
+    }
+
+    #[cfg(feature = "proxy")]
+    pub fn proxy_service(
+        &self,
+        proxy: Proxy,
+    ) -> io::Result<HyperService<hyper_proxy::ProxyConnector<HttpConnector>>> {
+        let mut inner = hyper_proxy::Proxy::new(hyper_proxy::Intercept::All, proxy.uri);
+        if let Some((username, password)) = &proxy.credentials {
+            let credentials = typed_headers::Credentials::basic(username, password)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            inner.set_authorization(credentials);
+        }
+
+        let http = self.http_connector(HttpConnector::new(DNS_THREADS));
+        let connector = hyper_proxy::ProxyConnector::from_proxy(http, inner)?;
+        Ok(self.service(connector))
+    }
+
+    /// Apply the socket options and connect timeout to `connector`.
+    fn http_connector<C: SocketOptions>(&self, mut connector: C) -> C {
+        connector.set_nodelay(self.nodelay);
+        connector.set_keepalive(self.tcp_keepalive);
+        connector.set_connect_timeout(self.connect_timeout);
+        connector
+    }
+
+    fn service<C>(&self, connector: C) -> HyperService<C>
+    where
+        C: Connect + Sync + 'static,
+    {
+        HyperService::new(self.client_builder().build(connector))
+    }
+
+    /// The hyper client builder with the pool and HTTP/2 settings applied.
+    fn client_builder(&self) -> hyper::client::Builder {
+        let mut builder = Client::builder();
+        builder
+            .keep_alive_timeout(self.pool_idle_timeout)
+            .max_idle_per_host(self.pool_max_idle_per_host)
+            .http2_only(self.http2_prior_knowledge);
+        builder
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+/// The options `Builder` sets on the sockets of a connector.
+trait SocketOptions {
+    fn set_nodelay(&mut self, nodelay: bool);
+    fn set_keepalive(&mut self, keepalive: Option<Duration>);
+    fn set_connect_timeout(&mut self, timeout: Option<Duration>);
+}
+
+impl<R> SocketOptions for HttpConnector<R> {
+    fn set_nodelay(&mut self, nodelay: bool) {
+        HttpConnector::set_nodelay(self, nodelay);
+    }
+
+    fn set_keepalive(&mut self, keepalive: Option<Duration>) {
+        HttpConnector::set_keepalive(self, keepalive);
+    }
+
+    fn set_connect_timeout(&mut self, timeout: Option<Duration>) {
+        HttpConnector::set_connect_timeout(self, timeout);
+    }
+}
+
+#[cfg(feature = "proxy")]
+impl Proxy {
+    pub fn new(uri: Uri) -> Self {
+        Proxy {
+            uri,
+            credentials: None,
+        }
+    }
+
+    /// Authenticate with the proxy using basic auth.
+    pub fn basic_auth(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    pub fn uri(&self) -> &Uri {
+        &self.uri
+    }
+}
+
+impl StaticResolver {
+    pub fn new() -> Self {
+        StaticResolver {
+            hosts: Arc::new(HashMap::new()),
+            fallback: GaiResolver::new(DNS_THREADS),
+        }
+    }
+
+    /// Connect to `addrs` instead of looking `host` up.
+    pub fn pin(mut self, host: impl Into<String>, addrs: Vec<IpAddr>) -> Self {
+        let host = host.into().to_ascii_lowercase();
+        Arc::make_mut(&mut self.hosts).insert(host, addrs);
+        self
+    }
+}
+
+impl Default for StaticResolver {
+    fn default() -> Self {
+        StaticResolver::new()
+    }
+}
+
+impl Resolve for StaticResolver {
+    type Addrs = vec::IntoIter<IpAddr>;
+    type Future = Box<dyn Future<Item = Self::Addrs, Error = io::Error> + Send>;
+
+    fn resolve(&self, name: Name) -> Self::Future {
+        match self.hosts.get(&name.as_str().to_ascii_lowercase()) {
+            Some(addrs) => Box::new(futures::future::ok(addrs.clone().into_iter())),
+            None => Box::new(
+                self.fallback
+                    .resolve(name)
+                    .map(|addrs| addrs.collect::<Vec<_>>().into_iter()),
+            ),
+        }
+    }
+}
+
+impl fmt::Debug for StaticResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StaticResolver")
+            .field("hosts", &self.hosts)
+            .finish()
+    }
+}
+
+impl<C> HyperService<C> {
+    pub fn new(client: Client<C, RusotoBody>) -> Self {
+        HyperService { client }
+    }
+}
+
+impl<C> HyperService<C>
+where
+    C: Connect + Sync + 'static,
+{
+    /// Open a pooled connection to the host of `uri` ahead of the first
+    /// dispatch, so that it doesn't pay for the TCP and TLS handshakes.
+    ///
+    /// This sends an unsigned `HEAD` request; whatever status comes back, the
+    /// connection is kept in the pool.
+    pub fn warmup(&self, uri: Uri) -> Warmup {
+        let mut request = Request::new(RusotoBody::empty());
+        *request.method_mut() = Method::HEAD;
+        *request.uri_mut() = uri;
+
+        Warmup {
+            inner: self.client.request(request),
+        }
+    }
+}
+
+impl<C> HttpClient<HyperService<C>>
+where
+    C: Connect + Sync + 'static,
+{
+    /// See `HyperService::warmup`.
+    pub fn warmup(&self, uri: Uri) -> Warmup {
+        self.get_ref().warmup(uri)
+    }
+}
+
+impl<C> Service<Request<RusotoBody>> for HyperService<C>
+where
+    C: Connect + Sync + 'static,
+{
+    type Response = Response<ResponseBody>;
+    type Error = io::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, request: Request<RusotoBody>) -> Self::Future {
+        ResponseFuture {
+            inner: self.client.request(request),
+        }
+    }
+}
+
+impl Future for ResponseFuture {
+    type Item = Response<ResponseBody>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = futures::try_ready!(self.inner.poll().map_err(into_io));
+        Ok(Async::Ready(response.map(|inner| ResponseBody { inner })))
+    }
+}
+
+impl Future for Warmup {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        futures::try_ready!(self.inner.poll().map_err(into_io));
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Body for ResponseBody {
+    type Item = Chunk;
+    type Error = io::Error;
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.inner.poll_data().map_err(into_io)
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+        self.inner.poll_trailers().map_err(into_io)
+    }
+}
+
+impl Payload for RusotoBody {
+    type Data = io::Cursor<bytes::Bytes>;
+    type Error = io::Error;
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        Body::poll_buf(self)
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+        Body::poll_trailers(self)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        Body::is_end_stream(self)
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.len().map(|len| len as u64)
+    }
+}
+
+fn into_io(e: hyper::Error) -> io::Error {
+    // Keep the kind of an underlying io error, e.g. a reset connection, so
+    // `Kind::of` can tell it apart from other failures.
+    let kind = match e.source().and_then(crate::error::io_kind) {
+        Some(kind) => kind,
+        None if e.is_connect() => io::ErrorKind::NotConnected,
+        None if is_incomplete(&e) => io::ErrorKind::UnexpectedEof,
+        None => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, e)
+}
+
+/// Whether the connection closed before the whole message was read, e.g. by
+/// a proxy that hangs up without sending a status line.
+///
+/// hyper 0.12 has no accessor for this, so it is told by its message, which
+/// has read "connection closed before message completed" from hyper 0.12.0
+/// through 0.12.35. `closed_connection_is_reported` catches a change.
+fn is_incomplete(e: &hyper::Error) -> bool {
+    e.to_string()
+        .starts_with("connection closed before message completed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Stream;
+    use rusoto_core::{request::DispatchSignedRequest, signature::SignedRequest, Region};
+    use std::{
+        env, fs,
+        io::{Read, Write},
+        net::TcpListener,
+        process, thread,
+    };
+    use tokio::runtime::current_thread::Runtime;
+
+    fn local_request(addr: std::net::SocketAddr) -> SignedRequest {
+        let region = Region::Custom {
+            name: "us-east-1".to_string(),
+            endpoint: format!("http://{}", addr),
+        };
+        SignedRequest::new("GET", "s3", &region, "/")
+    }
+
+    #[test]
+    fn http_client_talks_to_a_local_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            let _ = socket.read(&mut buf).unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+                .unwrap();
+        });
+
+        let client = HttpClient::http();
+        let mut rt = Runtime::new().unwrap();
+        let response = rt
+            .block_on(client.dispatch(local_request(addr), None))
+            .unwrap();
+        let body = rt.block_on(response.body.concat2()).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(response.status, http::StatusCode::OK);
+        assert_eq!(&body[..], b"ok");
+    }
+
+    #[test]
+    fn builder_keeps_the_pool_settings() {
+        let builder = Builder::new()
+            .pool_idle_timeout(Some(Duration::from_secs(15)))
+            .pool_max_idle_per_host(8);
+
+        assert_eq!(builder.pool_idle_timeout, Some(Duration::from_secs(15)));
+        assert_eq!(builder.pool_max_idle_per_host, 8);
+        // hyper only shows what its builder was set to in its `Debug` output.
+        let settings = format!("{:?}", builder.client_builder());
+        assert!(
+            settings.contains("keep_alive_timeout: Some(15s)"),
+            "{}",
+            settings
+        );
+        assert!(settings.contains("max_idle_per_host: 8"), "{}", settings);
+        let _client = builder.http();
+
+        let builder = Builder::new().pool_idle_timeout(None);
+        assert_eq!(builder.pool_idle_timeout, None);
+        let settings = format!("{:?}", builder.client_builder());
+        assert!(
+            settings.contains("keep_alive_timeout: None"),
+            "{}",
+            settings
+        );
+    }
+
+    #[test]
+    fn prior_knowledge_starts_with_the_http2_preface() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut preface = [0; 24];
+            socket.read_exact(&mut preface).unwrap();
+            // Hang up, the client only has to get as far as the preface.
+            preface
+        });
+
+        let client = Builder::new().http2_prior_knowledge(true).http();
+        let mut rt = Runtime::new().unwrap();
+        let _ = rt.block_on(client.dispatch(local_request(addr), Some(Duration::from_secs(5))));
+
+        assert_eq!(&server.join().unwrap(), b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+    }
+
+    #[cfg(feature = "proxy")]
+    #[test]
+    fn proxy_keeps_its_settings() {
+        let proxy = Proxy::new("http://proxy.local:3128".parse().unwrap());
+        assert_eq!(proxy.uri().host(), Some("proxy.local"));
+        assert_eq!(proxy.uri().port_part().map(|p| p.as_u16()), Some(3128));
+        assert!(proxy.credentials.is_none());
+
+        let proxy = proxy.basic_auth("user", "pass");
+        assert_eq!(
+            proxy.credentials,
+            Some(("user".to_string(), "pass".to_string()))
+        );
+        assert!(Builder::new().proxy(proxy).is_ok());
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    #[test]
+    fn https_client_builds_with_the_enabled_tls_feature() {
+        assert!(HttpClient::https().is_ok());
+        assert!(Builder::new()
+            .connect_timeout(Some(Duration::from_secs(1)))
+            .https()
+            .is_ok());
+    }
+
+    #[test]
+    fn warmup_opens_a_connection_the_dispatch_reuses() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Only one connection is accepted, so the dispatch has to reuse it.
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut methods = Vec::new();
+            for _ in 0..2 {
+                let mut buf = [0; 1024];
+                let n = socket.read(&mut buf).unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+                methods.push(request.split(' ').next().unwrap_or("").to_string());
+                socket
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                    .unwrap();
+            }
+            methods
+        });
+
+        let client = HttpClient::http();
+        let mut rt = Runtime::new().unwrap();
+        let uri = format!("http://{}/", addr).parse().unwrap();
+        rt.block_on(client.warmup(uri)).unwrap();
+        let dispatch = client.dispatch(local_request(addr), Some(Duration::from_secs(5)));
+        let response = rt.block_on(dispatch).unwrap();
+
+        assert_eq!(response.status, http::StatusCode::OK);
+        assert_eq!(server.join().unwrap(), vec!["HEAD", "GET"]);
+    }
+
+    #[test]
+    fn pinned_host_connects_to_the_pinned_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            let n = socket.read(&mut buf).unwrap();
+            socket
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            String::from_utf8_lossy(&buf[..n]).to_lowercase()
+        });
+
+        // `pinned.invalid` doesn't resolve, only the pin gets it anywhere.
+        let resolver = StaticResolver::new().pin("pinned.invalid", vec![addr.ip()]);
+        let client = HttpClient::new(Builder::new().http_service_with_resolver(resolver));
+        let region = Region::Custom {
+            name: "us-east-1".to_string(),
+            endpoint: format!("http://pinned.invalid:{}", addr.port()),
+        };
+        let request = SignedRequest::new("GET", "s3", &region, "/");
+
+        let mut rt = Runtime::new().unwrap();
+        let response = rt.block_on(client.dispatch(request, None)).unwrap();
+        assert_eq!(response.status, http::StatusCode::NO_CONTENT);
+        let request = server.join().unwrap();
+        assert!(
+            request.contains(&format!("host: pinned.invalid:{}", addr.port())),
+            "{}",
+            request
+        );
+    }
+
+    #[test]
+    fn pins_ignore_ascii_case() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            socket.read(&mut buf).unwrap();
+            socket
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+        });
+
+        let resolver = StaticResolver::new().pin("Pinned.Invalid", vec![addr.ip()]);
+        let client = HttpClient::new(Builder::new().http_service_with_resolver(resolver));
+        let region = Region::Custom {
+            name: "us-east-1".to_string(),
+            endpoint: format!("http://PINNED.invalid:{}", addr.port()),
+        };
+        let request = SignedRequest::new("GET", "s3", &region, "/");
+
+        let mut rt = Runtime::new().unwrap();
+        let response = rt.block_on(client.dispatch(request, None)).unwrap();
+        assert_eq!(response.status, http::StatusCode::NO_CONTENT);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn closed_connection_is_reported() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            // Read the request, then hang up without a status line.
+            let mut buf = [0; 1024];
+            let _ = socket.read(&mut buf).unwrap();
+        });
+
+        let client = HttpClient::http();
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt
+            .block_on(client.dispatch(local_request(addr), None))
+            .unwrap_err();
+        server.join().unwrap();
+
+        let msg = err.to_string();
+        assert!(msg.starts_with("ConnectionClosedError"), "{}", msg);
+        assert!(msg.contains("connection closed before"), "{}", msg);
+    }
+
+    /// Records the socket options it is given instead of applying them.
+    #[derive(Default)]
+    struct Recorded {
+        nodelay: Option<bool>,
+        keepalive: Option<Option<Duration>>,
+        connect_timeout: Option<Option<Duration>>,
+    }
+
+    impl SocketOptions for Recorded {
+        fn set_nodelay(&mut self, nodelay: bool) {
+            self.nodelay = Some(nodelay);
+        }
+
+        fn set_keepalive(&mut self, keepalive: Option<Duration>) {
+            self.keepalive = Some(keepalive);
+        }
+
+        fn set_connect_timeout(&mut self, timeout: Option<Duration>) {
+            self.connect_timeout = Some(timeout);
+        }
+    }
+
+    #[test]
+    fn socket_options_are_applied_to_the_connector() {
+        let connector = Builder::new().http_connector(Recorded::default());
+        assert_eq!(connector.nodelay, Some(false));
+        assert_eq!(connector.keepalive, Some(None));
+        assert_eq!(connector.connect_timeout, Some(None));
+
+        let connector = Builder::new()
+            .nodelay(true)
+            .tcp_keepalive(Some(Duration::from_secs(30)))
+            .connect_timeout(Some(Duration::from_secs(5)))
+            .http_connector(Recorded::default());
+        assert_eq!(connector.nodelay, Some(true));
+        assert_eq!(connector.keepalive, Some(Some(Duration::from_secs(30))));
+        assert_eq!(
+            connector.connect_timeout,
+            Some(Some(Duration::from_secs(5)))
+        );
+    }
+
+    #[test]
+    fn opened_file_is_sent_with_a_content_length() {
+        let path = env::temp_dir().join(format!("tower-rusoto-put-{}", process::id()));
+        fs::write(&path, b"file contents").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"file contents") {
+                let mut buf = [0; 1024];
+                let n = socket.read(&mut buf).unwrap();
+                assert!(n > 0, "connection closed before the body");
+                request.extend_from_slice(&buf[..n]);
+            }
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8_lossy(&request).to_lowercase()
+        });
+
+        // The blocking pool needs the threaded runtime.
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let body = rt.block_on(RusotoBody::open_file(path.clone())).unwrap();
+        let request = Request::put(format!("http://{}/key", addr))
+            .body(body)
+            .unwrap();
+        let response = rt
+            .block_on(HttpClient::http().dispatch_raw(request))
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(response.status, http::StatusCode::OK);
+
+        let request = server.join().unwrap();
+        assert!(request.contains("content-length: 13\r\n"), "{}", request);
+        assert!(!request.contains("transfer-encoding"), "{}", request);
+    }
+}
@@ -0,0 +1,54 @@
+use crate::{limit::Drain, Config, HttpClient};
+use std::sync::Arc;
+use tower_layer::Layer;
+
+/// Wraps an inner `HttpService` into an `HttpClient` with the default
+/// configuration.
+///
+/// Every client it builds shuts down on its own with `HttpClient::shutdown`.
+#[derive(Clone, Default)]
+pub struct HttpClientLayer {
+    config: Arc<Config>,
+}
+
+impl HttpClientLayer {
+    pub fn new() -> Self {
+        HttpClientLayer::default()
+    }
+}
+
+impl<S> Layer<S> for HttpClientLayer {
+    type Service = HttpClient<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HttpClient {
+            client: inner,
+            config: self.config.clone(),
+            drain: Arc::new(Drain::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockService;
+    use futures::Future;
+    use rusoto_core::{request::DispatchSignedRequest, signature::SignedRequest, Region};
+    use tower::ServiceBuilder;
+
+    #[test]
+    fn builds_a_client_in_a_service_builder_stack() {
+        let mock = MockService::new();
+        let client = ServiceBuilder::new()
+            .layer(HttpClientLayer::new())
+            .service(mock.clone());
+
+        let request = SignedRequest::new("GET", "s3", &Region::UsEast1, "/bucket/key");
+        client.dispatch(request, None).wait().unwrap();
+
+        let requests = mock.take_requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].uri().path(), "/bucket/key");
+    }
+}
@@ -1,173 +1,2869 @@
-use bytes::Buf;
-use futures::{Async, Future, Poll, Stream};
+use crate::error::Kind;
+use bytes::{Buf, Bytes};
+use futures::{Async, Poll, Stream};
+#[cfg(feature = "gzip")]
+use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING};
 use http::{
-    header::{HeaderMap, HeaderName, HeaderValue},
-    Method, Request,
+    header::{
+        HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, HOST, TRANSFER_ENCODING,
+        USER_AGENT,
+    },
+    uri::{self, Authority, PathAndQuery, Scheme},
+    Method, Request, StatusCode, Uri,
 };
 use rusoto_core::{
-    request::{DispatchSignedRequest, Headers, HttpDispatchError, HttpResponse},
+    request::{DispatchSignedRequest, HttpDispatchError, HttpResponse},
     signature::{SignedRequest, SignedRequestPayload},
-    ByteStream,
 };
-use std::{io, time::Duration};
+use std::{any::Any, collections::HashMap, fmt, io, net::Ipv6Addr, sync::Arc, time::Duration};
 use tokio_buf::BufStream;
-use tower_http::{Body, BodyExt, HttpService};
+use tokio_timer::Delay;
+use tower_http::{Body, HttpService};
+use tower_service::Service;
 
+mod body;
+pub mod breaker;
+#[cfg(feature = "compat")]
+pub mod compat;
+#[cfg(feature = "debug-log")]
+mod debug_log;
+mod error;
+pub mod future;
+#[cfg(feature = "gzip")]
+mod gzip;
+#[cfg(feature = "hyper")]
+pub mod hyper;
+mod layer;
+mod limit;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
+mod read;
+pub mod retry;
+mod shadow;
+mod shared;
+#[cfg(feature = "tracing")]
+mod trace;
+
+pub use crate::body::{OpenFile, RusotoBody};
+pub use crate::error::ErrorContext;
+pub use crate::layer::HttpClientLayer;
+#[cfg(feature = "metrics")]
+pub use crate::metrics::{Metrics, TAG_HEADER};
+pub use crate::read::{into_async_read, BodyReader};
+pub use crate::shared::Shared;
+
+pub(crate) const X_REQUEST_ID: &str = "x-request-id";
+
+const DEFAULT_USER_AGENT: &str = concat!("tower-rusoto/", env!("CARGO_PKG_VERSION"));
+
+/// Dispatches rusoto `SignedRequest`s over an inner `HttpService`.
+///
+/// `DispatchSignedRequest::dispatch` only gets `&self`, while calling the
+/// inner service needs `&mut`, so every dispatch is made on a clone of the
+/// inner service. This is why `T` has to be `Clone`, and it should be cheap
+/// to clone: a handle to a shared connection pool, like hyper's `Client`,
+/// rather than the pool itself.
+///
+/// Services that are not `Clone`, such as a `tower-balance` load balancer,
+/// should be wrapped in a `tower-buffer` `Buffer`, which drives the balancer
+/// on a task of its own and hands out cheap handles to it. Each dispatch
+/// waits for its handle to become ready before calling it, so the balancer
+/// still sees every request and can pick the least loaded endpoint. `Shared`
+/// only suits a service whose calls return right away, such as a single
+/// connection.
+///
+/// rusoto's `Headers` hold one value per header name, so a response header
+/// that appears more than once is joined into one value with `", "`. The
+/// exception is `Set-Cookie`, whose values can contain commas themselves:
+/// they are joined with `"\n"`, so split them with `str::lines`.
 #[derive(Clone)]
 pub struct HttpClient<T> {
     client: T,
+    config: Arc<Config>,
+    /// Shared by clones, but not by other clients built from the same
+    /// `HttpClientLayer`, so `shutdown` only stops this client.
+    drain: Arc<limit::Drain>,
 }
 
-pub struct RusotoBody {
-    inner: Option<SignedRequestPayload>,
+#[must_use]
+pub struct HttpClientBuilder<T> {
+    client: T,
+    config: Config,
+}
+
+#[derive(Clone)]
+struct Config {
+    user_agent: Option<HeaderValue>,
+    default_headers: HeaderMap,
+    default_content_type: Option<HeaderValue>,
+    methods: HashMap<String, Method>,
+    host_override: Option<Arc<dyn Fn(&SignedRequest) -> Option<HeaderValue> + Send + Sync>>,
+    response_header_filter: Option<Arc<dyn Fn(&HeaderName) -> bool + Send + Sync>>,
+    header_decode: HeaderDecodePolicy,
+    request_id: Option<Arc<dyn Fn() -> HeaderValue + Send + Sync>>,
+    upload_progress: Option<Progress>,
+    download_progress: Option<Progress>,
+    on_request: Option<Arc<dyn Fn(&mut Request<RusotoBody>) + Send + Sync>>,
+    on_complete: Option<Arc<OnComplete>>,
+    max_chunk_size: usize,
+    base_path: Option<String>,
+    scheme: Option<Scheme>,
+    port: Option<u16>,
+    #[cfg(feature = "gzip")]
+    gzip: bool,
+    #[cfg(feature = "gzip")]
+    accept_gzip: bool,
+    max_response_bytes: Option<usize>,
+    max_header_bytes: Option<usize>,
+    deadlines: DeadlineConfig,
+    timeout: Option<Duration>,
+    honor_dispatch_timeout: bool,
+    strict_body: bool,
+    check_content_length: bool,
+    limit: Option<Arc<limit::Limit>>,
+    shadow: Option<shadow::Mirror>,
+    error_formatter: Option<Arc<error::Formatter>>,
+    response_trailers: bool,
+    treat_5xx_as_error: bool,
+    status_policy: Option<Arc<dyn Fn(StatusCode) -> bool + Send + Sync>>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<dyn Metrics>>,
 }
 
 struct BodyStream<T> {
     body: T,
+    max_bytes: Option<usize>,
+    received: usize,
+    deadline: Option<Delay>,
+    progress: Option<Progress>,
+    on_timeout: Option<OnBodyTimeout>,
+}
+
+type OnComplete = dyn Fn(Duration, Result<&HttpResponse, &HttpDispatchError>) + Send + Sync;
+
+/// Called with the number of body bytes transferred so far.
+pub(crate) type Progress = Arc<dyn Fn(u64) + Send + Sync>;
+
+/// Called when a response body outlives the total deadline.
+pub(crate) type OnBodyTimeout = Box<dyn Fn() + Send + Sync>;
+
+/// What to do with response header values that are not valid UTF-8, which
+/// rusoto's `Headers` can't hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderDecodePolicy {
+    /// Replace invalid sequences with `U+FFFD`.
+    Lossy,
+    /// Drop the value, and the header if none of its values are valid.
+    Skip,
+    /// Fail the dispatch.
+    Error,
+}
+
+/// Deadlines applied to every dispatch on top of the timeout rusoto passes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DeadlineConfig {
+    /// Bounds the time until the response head has been received, counted
+    /// from the dispatch. This includes waiting for a concurrency permit,
+    /// connecting and the TLS handshake, but it is not a connect timeout: to
+    /// bound only connecting, set one on the inner service, e.g.
+    /// `hyper::Builder::connect_timeout`.
+    pub response_head: Option<Duration>,
+    /// Bounds the whole exchange, including streaming the response body.
+    pub total: Option<Duration>,
 }
 
 impl<T> HttpClient<T> {
     pub fn new(client: T) -> Self {
-        HttpClient { client }
+        HttpClient::builder(client).build()
+    }
+
+    pub fn with_user_agent(client: T, user_agent: HeaderValue) -> Self {
+        HttpClient::builder(client).user_agent(user_agent).build()
+    }
+
+    pub fn builder(client: T) -> HttpClientBuilder<T> {
+        HttpClientBuilder {
+            client,
+            config: Config::default(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.client
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.client
+    }
+
+    pub fn into_inner(self) -> T {
+        self.client
+    }
+
+    /// Check that `request` can be turned into an http request, with the
+    /// same checks `dispatch` makes, without sending it.
+    ///
+    /// The `on_request` hook isn't called.
+    pub fn validate(&self, request: &SignedRequest) -> Result<(), HttpDispatchError> {
+        self.config.build_parts(request).map(drop)
+    }
+
+    /// Stop taking new dispatches, across all clones of the client, and wait
+    /// for the ones in flight.
+    ///
+    /// Dispatches made after this fail right away with a `ShutdownError`.
+    /// The returned future resolves once every earlier dispatch future has
+    /// resolved or been dropped; response bodies may still be streaming.
+    pub fn shutdown(&self) -> future::Shutdown {
+        self.drain.close();
+        future::Shutdown::new(self.drain.clone())
+    }
+
+    /// Like `collect_body`, but never reserve more than the
+    /// `max_response_bytes` of this client up front.
+    pub fn collect_body(&self, response: HttpResponse) -> future::CollectBody {
+        future::CollectBody::new(response, self.config.max_response_bytes)
     }
 }
 
-impl<T> DispatchSignedRequest for HttpClient<T>
-where
-    T: HttpService<RusotoBody> + Clone,
-    T::Future: Send + 'static,
-    T::ResponseBody: Send + 'static,
-    <T::ResponseBody as Body>::Error: Into<io::Error>,
-    T::Error: Into<io::Error> + Send + 'static,
-{
-    type Future = Box<Future<Item = HttpResponse, Error = HttpDispatchError> + Send + 'static>;
+impl<T> HttpClientBuilder<T> {
+    /// Set the `User-Agent` sent when the signed request doesn't carry one.
+    pub fn user_agent(mut self, user_agent: HeaderValue) -> Self {
+        self.config.user_agent = Some(user_agent);
+        self
+    }
 
-    fn dispatch(&self, request: SignedRequest, timeout: Option<Duration>) -> Self::Future {
-        assert!(timeout.is_none(), "timeout is not supported at this level");
+    /// Don't add a `User-Agent` header to outgoing requests.
+    pub fn no_user_agent(mut self) -> Self {
+        self.config.user_agent = None;
+        self
+    }
+
+    /// Add a header to every outgoing request that doesn't already carry a
+    /// header with the same name.
+    ///
+    /// These headers are added after signing, so they are not covered by the
+    /// request signature.
+    pub fn default_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.config.default_headers.append(name, value);
+        self
+    }
+
+    /// Map the method string `name` of signed requests to `method`, for verbs
+    /// beyond the standard ones.
+    pub fn method(mut self, name: impl Into<String>, method: Method) -> Self {
+        self.config.methods.insert(name.into(), method);
+        self
+    }
+
+    /// Send `Content-Type: content_type` with requests that have a body but no
+    /// `Content-Type` of their own.
+    ///
+    /// Like default headers this is added after signing. AWS accepts that for
+    /// headers that are not signed, but a service that requires the header to
+    /// be signed will reject the request.
+    pub fn default_content_type(mut self, content_type: HeaderValue) -> Self {
+        self.config.default_content_type = Some(content_type);
+        self
+    }
+
+    /// Compute the `Host` header from the signed request, independent of the
+    /// host the connection is made to. Returning `None` keeps the signed value.
+    ///
+    /// rusoto signs the `Host` header, so the override has to match the host
+    /// the request was signed for.
+    pub fn host_override<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&SignedRequest) -> Option<HeaderValue> + Send + Sync + 'static,
+    {
+        self.config.host_override = Some(Arc::new(f));
+        self
+    }
+
+    /// Set how response header values that are not valid UTF-8 are handled.
+    /// Defaults to `HeaderDecodePolicy::Lossy`.
+    pub fn header_decode_policy(mut self, policy: HeaderDecodePolicy) -> Self {
+        self.config.header_decode = policy;
+        self
+    }
+
+    /// Tag every request with an `X-Request-Id` header produced by `f`, unless
+    /// the signed request already carries one. The id is included in the
+    /// message of any error the request fails with.
+    pub fn request_id<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> HeaderValue + Send + Sync + 'static,
+    {
+        self.config.request_id = Some(Arc::new(f));
+        self
+    }
+
+    /// Call `f` with every request right before it is handed to the inner
+    /// service.
+    ///
+    /// Changing a signed header, the path or the query breaks the signature
+    /// and the request will be rejected.
+    pub fn on_request<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&mut Request<RusotoBody>) + Send + Sync + 'static,
+    {
+        self.config.on_request = Some(Arc::new(f));
+        self
+    }
+
+    /// Call `f` with the time it took to get a response, or to fail, every
+    /// time a dispatch completes.
+    ///
+    /// The time is measured up to the response headers, reading the body is
+    /// only included when `response_trailers` is enabled. Dispatches made
+    /// with `dispatch_http` only report failures, since they never produce a
+    /// rusoto `HttpResponse`.
+    pub fn on_complete<F>(mut self, f: F) -> Self
+    where
+        F: Fn(Duration, Result<&HttpResponse, &HttpDispatchError>) + Send + Sync + 'static,
+    {
+        self.config.on_complete = Some(Arc::new(f));
+        self
+    }
+
+    /// Call `f` with the number of request body bytes sent so far, each time
+    /// the inner service takes a chunk.
+    ///
+    /// The callback runs inline while the body is polled, so it should be
+    /// cheap; hand heavy work off to another task.
+    pub fn on_upload_progress<F>(mut self, f: F) -> Self
+    where
+        F: Fn(u64) + Send + Sync + 'static,
+    {
+        self.config.upload_progress = Some(Arc::new(f));
+        self
+    }
+
+    /// Call `f` with the number of response body bytes received so far, each
+    /// time rusoto reads a chunk.
+    ///
+    /// Like `on_upload_progress`, the callback runs inline.
+    pub fn on_download_progress<F>(mut self, f: F) -> Self
+    where
+        F: Fn(u64) + Send + Sync + 'static,
+    {
+        self.config.download_progress = Some(Arc::new(f));
+        self
+    }
+
+    /// Send a copy of every request to `service` as well, with its URI
+    /// pointed at `authority`, e.g. to try out a new endpoint, and discard
+    /// its response.
+    ///
+    /// **The copy keeps every header of the signed request**, including
+    /// `Authorization`, `X-Amz-Security-Token` and `Host`, since changing any
+    /// of them would invalidate the signature. Only mirror to endpoints that
+    /// are trusted with production credentials.
+    ///
+    /// The copies are spawned onto the default executor once the request is
+    /// actually sent, so requests failed by `concurrency_limit` or
+    /// `shutdown` aren't mirrored. Requests with a streaming payload can
+    /// only be sent once and are not copied.
+    pub fn shadow<S>(mut self, service: S, authority: Authority) -> Self
+    where
+        S: HttpService<RusotoBody> + Clone + Send + 'static,
+        S::Future: Send + 'static,
+    {
+        self.config.shadow = Some(shadow::mirror(service, authority));
+        self
+    }
+
+    /// Only pass the response headers for which `f` returns `true` on to
+    /// rusoto, e.g. to strip hop-by-hop headers added by a proxy.
+    pub fn response_header_filter<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&HeaderName) -> bool + Send + Sync + 'static,
+    {
+        self.config.response_header_filter = Some(Arc::new(f));
+        self
+    }
+
+    /// Set the largest chunk a buffered request payload is split into.
+    ///
+    /// Defaults to 64KiB.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    pub fn max_chunk_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "max_chunk_size must be greater than zero");
+        self.config.max_chunk_size = size;
+        self
+    }
+
+    /// Prefix every request path with `base_path`, for endpoints that are
+    /// mounted under a sub path such as `https://gw.example.com/storage`.
+    pub fn base_path(mut self, base_path: impl Into<String>) -> Self {
+        self.config.base_path = Some(base_path.into());
+        self
+    }
+
+    /// Always use `scheme` when building the request URI instead of the one
+    /// from the signed request, e.g. to talk plain HTTP to a TLS terminating
+    /// sidecar.
+    pub fn force_scheme(mut self, scheme: Scheme) -> Self {
+        self.config.scheme = Some(scheme);
+        self
+    }
+
+    /// Connect to `port` instead of the one in the signed hostname, or the
+    /// scheme's default port if it has none.
+    ///
+    /// rusoto already puts the port of a custom endpoint such as
+    /// `http://localhost:4566` into the hostname, so this is only needed when
+    /// the port has to differ from the one that was signed.
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = Some(port);
+        self
+    }
+
+    /// Transparently decompress responses sent with `Content-Encoding: gzip`.
+    ///
+    /// The `Content-Encoding` and `Content-Length` headers of decompressed
+    /// responses are not passed on to rusoto.
+    #[cfg(feature = "gzip")]
+    pub fn gzip(mut self, enabled: bool) -> Self {
+        self.config.gzip = enabled;
+        self
+    }
+
+    /// Ask for gzip encoded responses by sending `Accept-Encoding: gzip`, and
+    /// decompress them as with `gzip`.
+    ///
+    /// The header is added after signing, which SigV4 allows for headers that
+    /// are not listed as signed. Requests that already carry an
+    /// `Accept-Encoding` keep the signed value.
+    #[cfg(feature = "gzip")]
+    pub fn accept_gzip(mut self, enabled: bool) -> Self {
+        self.config.accept_gzip = enabled;
+        self
+    }
+
+    /// Fail the response body stream once more than `max` bytes have been
+    /// received, regardless of what the `Content-Length` header claims.
+    ///
+    /// With the `gzip` feature the limit applies to the decompressed body as
+    /// well, so a small compressed body can't inflate without bound.
+    pub fn max_response_bytes(mut self, max: usize) -> Self {
+        self.config.max_response_bytes = Some(max);
+        self
+    }
+
+    /// Fail requests whose header names and values add up to more than `max`
+    /// bytes before they are sent, rather than having a gateway drop them.
+    pub fn max_header_bytes(mut self, max: usize) -> Self {
+        self.config.max_header_bytes = Some(max);
+        self
+    }
+
+    /// Set the timeout used when rusoto doesn't pass one to `dispatch`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = Some(timeout);
+        self
+    }
+
+    /// Whether a timeout passed to `dispatch` overrides the one set with
+    /// `timeout`. Defaults to `true`; when disabled the timeout rusoto passes
+    /// is ignored.
+    pub fn honor_dispatch_timeout(mut self, honor: bool) -> Self {
+        self.config.honor_dispatch_timeout = honor;
+        self
+    }
+
+    /// Fail `POST`, `PUT` and `PATCH` requests whose payload is missing or an
+    /// empty buffer instead of sending them without a body. Defaults to `false`.
+    pub fn strict_body(mut self, strict: bool) -> Self {
+        self.config.strict_body = strict;
+        self
+    }
+
+    /// Read the whole response body before handing it to rusoto, so that any
+    /// trailers the server sends can be added to the response headers.
+    /// Defaults to `false`.
+    pub fn response_trailers(mut self, enabled: bool) -> Self {
+        self.config.response_trailers = enabled;
+        self
+    }
+
+    /// Produce the messages of dispatch errors with `f` rather than as
+    /// `<kind>: <message>`, e.g. `TimeoutError: GET https://...: request timed
+    /// out`.
+    ///
+    /// Errors yielded while rusoto reads a response body are not affected.
+    /// `retry::RetryPolicy` tells failures apart by the default prefixes, so
+    /// with a formatter it only retries `5xx` responses.
+    pub fn error_formatter<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&ErrorContext<'_>) -> String + Send + Sync + 'static,
+    {
+        self.config.error_formatter = Some(Arc::new(f));
+        self
+    }
+
+    /// Fail the dispatch with a `ServerStatusError` when the response has a
+    /// 5xx status instead of handing it to rusoto, e.g. so a retry layer sees
+    /// a 502 from a proxy. Defaults to `false`.
+    pub fn treat_5xx_as_error(mut self, enabled: bool) -> Self {
+        self.config.treat_5xx_as_error = enabled;
+        self
+    }
+
+    /// Fail the dispatch when `f` returns `false` for the response status,
+    /// e.g. to accept a 207 from an internal service but not a 418.
+    ///
+    /// This decides for every status, so it takes precedence over
+    /// `treat_5xx_as_error`. A rejected 5xx fails with a `ServerStatusError`
+    /// like there, any other status with a `StatusError`.
+    pub fn status_policy<F>(mut self, f: F) -> Self
+    where
+        F: Fn(StatusCode) -> bool + Send + Sync + 'static,
+    {
+        self.config.status_policy = Some(Arc::new(f));
+        self
+    }
+
+    /// Fail requests with a buffered payload whose length differs from their
+    /// signed `Content-Length`, which points at a signing bug. Defaults to
+    /// `false`.
+    pub fn check_content_length(mut self, enabled: bool) -> Self {
+        self.config.check_content_length = enabled;
+        self
+    }
+
+    /// Allow at most `max` dispatches to be in flight at once, across all
+    /// clones of the client. Further dispatches wait until one of them gets
+    /// its response or is dropped.
+    pub fn max_concurrency(mut self, max: usize) -> Self {
+        assert!(max > 0, "max_concurrency must be greater than zero");
+        self.config.limit = Some(Arc::new(limit::Limit::new(max)));
+        self
+    }
+
+    /// Apply `deadlines` to every dispatch, on top of the timeout rusoto
+    /// passes or the one set with `timeout`. Until the response head is in,
+    /// whichever of them expires first fails the dispatch; after that only
+    /// `total` bounds the body, including while `collect_body` reads it.
+    pub fn deadlines(mut self, deadlines: DeadlineConfig) -> Self {
+        self.config.deadlines = deadlines;
+        self
+    }
+
+    /// Report dispatch counters to `metrics`.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(mut self, metrics: impl Metrics + 'static) -> Self {
+        self.config.metrics = Some(Arc::new(metrics));
+        self
+    }
+
+    /// Create the client. Its clones share the configuration, the
+    /// concurrency limit and the shutdown state.
+    pub fn build(self) -> HttpClient<T> {
+        HttpClient {
+            client: self.client,
+            config: Arc::new(self.config),
+            drain: Arc::new(limit::Drain::new()),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            user_agent: Some(HeaderValue::from_static(DEFAULT_USER_AGENT)),
+            default_headers: HeaderMap::new(),
+            default_content_type: None,
+            methods: HashMap::new(),
+            host_override: None,
+            response_header_filter: None,
+            header_decode: HeaderDecodePolicy::Lossy,
+            request_id: None,
+            upload_progress: None,
+            download_progress: None,
+            on_request: None,
+            on_complete: None,
+            max_chunk_size: body::DEFAULT_MAX_CHUNK_SIZE,
+            base_path: None,
+            scheme: None,
+            port: None,
+            #[cfg(feature = "gzip")]
+            gzip: false,
+            #[cfg(feature = "gzip")]
+            accept_gzip: false,
+            max_response_bytes: None,
+            max_header_bytes: None,
+            deadlines: DeadlineConfig::default(),
+            timeout: None,
+            honor_dispatch_timeout: true,
+            strict_body: false,
+            check_content_length: false,
+            limit: None,
+            shadow: None,
+            error_formatter: None,
+            response_trailers: false,
+            treat_5xx_as_error: false,
+            status_policy: None,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+        }
+    }
+}
+
+impl Config {
+    /// Whether a response with `status` should fail the dispatch.
+    fn is_error_status(&self, status: StatusCode) -> bool {
+        match &self.status_policy {
+            Some(policy) => !policy(status),
+            None => self.treat_5xx_as_error && status.is_server_error(),
+        }
+    }
+
+    pub(crate) fn error<M: fmt::Display>(&self, kind: Kind, msg: M) -> HttpDispatchError {
+        error::format(self.error_formatter.as_ref().map(|f| &**f), kind, msg)
+    }
+
+    /// Like `error`, for a failure while sending the request `target`.
+    pub(crate) fn error_at<M: fmt::Display>(
+        &self,
+        kind: Kind,
+        target: &error::Target,
+        msg: M,
+    ) -> HttpDispatchError {
+        let formatter = self.error_formatter.as_ref().map(|f| &**f);
+        error::format_at(formatter, kind, Some(target), msg)
+    }
+
+    #[cfg(feature = "gzip")]
+    fn gunzip(&self, headers: &HeaderMap) -> bool {
+        (self.gzip || self.accept_gzip)
+            && headers
+                .get(CONTENT_ENCODING)
+                .map(|v| v.as_bytes().eq_ignore_ascii_case(b"gzip"))
+                .unwrap_or(false)
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    fn gunzip(&self, _: &HeaderMap) -> bool {
+        false
+    }
+
+    fn build_request(
+        &self,
+        request: SignedRequest,
+    ) -> Result<Request<RusotoBody>, HttpDispatchError> {
+        let (method, headers, uri) = self.build_parts(&request)?;
+
+        let body = RusotoBody::from(request.payload)
+            .with_max_chunk_size(self.max_chunk_size)
+            .with_progress(self.upload_progress.clone());
+
+        let mut request = match Request::builder()
+            .method(method.clone())
+            .uri(uri.clone())
+            .body(body)
+        {
+            Ok(request) => request,
+            Err(e) => {
+                let msg = format!("{} {}: {}", method, without_query(&uri), e);
+                return Err(self.error(Kind::Build, msg));
+            }
+        };
+
+        *request.headers_mut() = headers;
+
+        if let Some(on_request) = &self.on_request {
+            on_request(&mut request);
+        }
+
+        Ok(request)
+    }
 
+    /// Run every check of `build_request` that doesn't need the payload by
+    /// value, returning the method, headers and URI to send.
+    fn build_parts(
+        &self,
+        request: &SignedRequest,
+    ) -> Result<(Method, HeaderMap, Uri), HttpDispatchError> {
         let method = match request.method().as_ref() {
             "POST" => Method::POST,
             "PUT" => Method::PUT,
             "DELETE" => Method::DELETE,
             "GET" => Method::GET,
             "HEAD" => Method::HEAD,
-            v => unimplemented!(),
+            "PATCH" => Method::PATCH,
+            "OPTIONS" => Method::OPTIONS,
+            "TRACE" => Method::TRACE,
+            "CONNECT" => {
+                let msg = "CONNECT is not supported, requests are always sent to a path on the \
+                           signed host";
+                return Err(self.error(Kind::Build, msg));
+            }
+            v => match self.methods.get(v) {
+                Some(method) => method.clone(),
+                None => {
+                    return Err(self.error(Kind::Build, format!("unsupported method: {}", v)));
+                }
+            },
         };
 
+        if request.hostname().is_empty() {
+            let msg = format!(
+                "{} {}: the request has no hostname, check the region or endpoint",
+                method,
+                request.canonical_path()
+            );
+            return Err(self.error(Kind::Build, msg));
+        }
+
         let mut headers = HeaderMap::new();
         for h in request.headers().iter() {
+            #[cfg(feature = "metrics")]
+            {
+                if h.0 == metrics::TAG_HEADER {
+                    continue;
+                }
+            }
+
             let header_name = match h.0.parse::<HeaderName>() {
                 Ok(name) => name,
-                Err(err) => unimplemented!(),
+                Err(err) => {
+                    let msg = format!("invalid header name {:?}: {}", h.0, err);
+                    return Err(self.error(Kind::Build, msg));
+                }
             };
             for v in h.1.iter() {
                 let header_value = match HeaderValue::from_bytes(v) {
                     Ok(value) => value,
-                    Err(err) => unimplemented!(),
+                    Err(err) => {
+                        let msg = format!(
+                            "invalid value {:?} for header {}: {}",
+                            String::from_utf8_lossy(v),
+                            header_name,
+                            err
+                        );
+                        return Err(self.error(Kind::Build, msg));
+                    }
                 };
                 headers.append(&header_name, header_value);
             }
         }
 
-        // TODO(lucio): set user-agent
-
-        let mut uri = format!(
-            "{}://{}{}",
-            request.scheme(),
-            request.hostname(),
-            request.canonical_path()
-        );
+        if let Some(host_override) = &self.host_override {
+            if let Some(host) = host_override(&request) {
+                headers.insert(HOST, host);
+            }
+        }
 
-        if !request.canonical_query_string().is_empty() {
-            uri += &format!("?{}", request.canonical_query_string());
+        for name in self.default_headers.keys() {
+            if !headers.contains_key(name) {
+                for value in self.default_headers.get_all(name) {
+                    headers.append(name, value.clone());
+                }
+            }
         }
 
-        let mut request = Request::builder()
-            .method(method)
-            .uri(uri)
-            .body(RusotoBody::from(request.payload))
-            .map_err(|e| format!("RequestBuildingError: {}", e))
-            .unwrap();
+        if let Some(user_agent) = &self.user_agent {
+            if !headers.contains_key(USER_AGENT) {
+                headers.insert(USER_AGENT, user_agent.clone());
+            }
+        }
 
-        *request.headers_mut() = headers;
+        #[cfg(feature = "gzip")]
+        {
+            if self.accept_gzip && !headers.contains_key(ACCEPT_ENCODING) {
+                headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+            }
+        }
 
-        let request = {
-            let mut client = self.client.clone();
-            client.call(request)
-        };
-
-        let fut = request
-            .and_then(|response| {
-                let status = response.status();
-                let headers = Headers::new(response.headers().iter().map(|(h, v)| {
-                    let value_string = v.to_str().unwrap().to_owned();
-                    (h.as_str(), value_string)
-                }));
-                let body = response.into_body().into_buf_stream();
-                let body = BodyStream { body };
-
-                Ok(HttpResponse {
-                    status: status,
-                    headers: headers,
-                    body: ByteStream::new(body),
-                })
-            })
-            .map_err(|e| HttpDispatchError::new(format!("DispatchError: {}", e.into())));
+        if let Some(request_id) = &self.request_id {
+            if !headers.contains_key(X_REQUEST_ID) {
+                headers.insert(X_REQUEST_ID, request_id());
+            }
+        }
 
-        Box::new(fut)
-    }
-}
+        // Some endpoints reject a bodyless PUT or POST that carries neither a
+        // `Content-Length` nor a `Transfer-Encoding`.
+        let expects_body =
+            method == Method::POST || method == Method::PUT || method == Method::PATCH;
 
-impl Body for RusotoBody {
-    type Item = io::Cursor<Vec<u8>>;
-    type Error = io::Error;
+        if self.strict_body && expects_body {
+            let empty = match &request.payload {
+                None => true,
+                Some(SignedRequestPayload::Buffer(buf)) => buf.is_empty(),
+                Some(SignedRequestPayload::Stream(_)) => false,
+            };
+            if empty {
+                let msg = format!(
+                    "{} {}{}: request body is empty",
+                    method,
+                    request.hostname(),
+                    request.canonical_path()
+                );
+                return Err(self.error(Kind::Build, msg));
+            }
+        }
 
-    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        match &mut self.inner {
-            Some(SignedRequestPayload::Buffer(buf)) => {
-                if !buf.is_empty() {
-                    let buf = io::Cursor::new(buf.split_off(0));
-                    Ok(Async::Ready(Some(buf)))
-                } else {
-                    Ok(Async::Ready(None))
+        if self.check_content_length {
+            if let (Some(SignedRequestPayload::Buffer(buf)), Some(content_length)) =
+                (&request.payload, headers.get(CONTENT_LENGTH))
+            {
+                let matches = content_length
+                    .to_str()
+                    .ok()
+                    .and_then(|v| v.parse::<usize>().ok())
+                    .map(|len| len == buf.len())
+                    .unwrap_or(false);
+                if !matches {
+                    let msg = format!(
+                        "{} {}{}: Content-Length {:?} doesn't match the {} byte payload",
+                        method,
+                        request.hostname(),
+                        request.canonical_path(),
+                        content_length,
+                        buf.len()
+                    );
+                    return Err(self.error(Kind::Build, msg));
                 }
             }
-            Some(SignedRequestPayload::Stream(stream)) => match stream.poll()? {
-                Async::Ready(Some(buffer)) => Ok(Async::Ready(Some(io::Cursor::new(buffer)))),
-                Async::Ready(None) => Ok(Async::Ready(None)),
-                Async::NotReady => Ok(Async::NotReady),
+        }
+
+        if let Some(content_type) = &self.default_content_type {
+            if request.payload.is_some() && !headers.contains_key(CONTENT_TYPE) {
+                headers.insert(CONTENT_TYPE, content_type.clone());
+            }
+        }
+
+        if expects_body
+            && request.payload.is_none()
+            && !headers.contains_key(CONTENT_LENGTH)
+            && !headers.contains_key(TRANSFER_ENCODING)
+        {
+            headers.insert(CONTENT_LENGTH, HeaderValue::from_static("0"));
+        }
+
+        if let Some(max) = self.max_header_bytes {
+            let size = headers
+                .iter()
+                .map(|(name, value)| name.as_str().len() + value.len())
+                .sum::<usize>();
+            if size > max {
+                let msg = format!(
+                    "{} {}{}: headers take {} bytes, more than the limit of {}",
+                    method,
+                    request.hostname(),
+                    request.canonical_path(),
+                    size,
+                    max
+                );
+                return Err(self.error(Kind::Build, msg));
+            }
+        }
+
+        let uri = match self.uri(request) {
+            Ok(uri) => uri,
+            Err(e) => {
+                let msg = format!(
+                    "{} {}://{}{}: {}",
+                    method,
+                    request.scheme(),
+                    request.hostname(),
+                    request.canonical_path(),
+                    e
+                );
+                return Err(self.error(Kind::Build, msg));
+            }
+        };
+
+        Ok((method, headers, uri))
+    }
+
+    fn uri(&self, request: &SignedRequest) -> Result<Uri, String> {
+        let scheme = match &self.scheme {
+            Some(scheme) => scheme.clone(),
+            None => match request.scheme().as_str() {
+                "http" => Scheme::HTTP,
+                "https" => Scheme::HTTPS,
+                scheme => {
+                    return Err(format!(
+                        "unsupported scheme {:?}, expected \"http\" or \"https\"",
+                        scheme
+                    ));
+                }
             },
-            None => Ok(Async::Ready(None)),
+        };
+
+        let mut hostname = request.hostname();
+        // IPv6 literals have to be bracketed to be told apart from a port.
+        if hostname.parse::<Ipv6Addr>().is_ok() {
+            hostname = format!("[{}]", hostname);
+        }
+        let authority = Authority::from_shared(Bytes::from(hostname.clone()))
+            .map_err(|e| format!("invalid hostname {:?}: {}", hostname, e))?;
+        let authority = match self.port {
+            Some(port) => {
+                let authority = format!("{}:{}", authority.host(), port);
+                Authority::from_shared(Bytes::from(authority.clone()))
+                    .map_err(|e| format!("invalid authority {:?}: {}", authority, e))?
+            }
+            None => authority,
+        };
+
+        let path = request.canonical_path();
+        let path = match &self.base_path {
+            Some(base_path) => prefix_path(base_path, &path),
+            None => path,
+        };
+        let mut path_and_query = encode_path(&path);
+        if path_and_query.is_empty() {
+            path_and_query.push('/');
+        }
+
+        // `PathAndQuery` keeps the bytes it is given as they are, so the query
+        // on the wire is exactly the canonical query string that was signed.
+        let query = request.canonical_query_string();
+        if !query.is_empty() {
+            path_and_query.push('?');
+            path_and_query.push_str(query);
+        }
+
+        let path_and_query = PathAndQuery::from_shared(Bytes::from(path_and_query.clone()))
+            .map_err(|e| {
+                // Leave the query out, it may carry a presigned signature.
+                let path = path_and_query.split('?').next().unwrap_or("");
+                format!("invalid path or query for {:?}: {}", path, e)
+            })?;
+
+        let mut parts = uri::Parts::default();
+        parts.scheme = Some(scheme);
+        parts.authority = Some(authority);
+        parts.path_and_query = Some(path_and_query);
+
+        Uri::from_parts(parts).map_err(|e| e.to_string())
+    }
+}
+
+/// Read the whole body of `response` into a single `Bytes`, sized up front
+/// from its `Content-Length` when there is one.
+///
+/// At most 4 MiB are reserved before the body arrives, the buffer grows as
+/// the rest comes in. `HttpClient::collect_body` also keeps the reserve
+/// within `max_response_bytes`.
+pub fn collect_body(response: HttpResponse) -> future::CollectBody {
+    future::CollectBody::new(response, None)
+}
+
+/// Copy a signed request if its payload can be replayed.
+pub(crate) fn try_clone_request(request: &SignedRequest) -> Option<SignedRequest> {
+    let payload = match &request.payload {
+        Some(SignedRequestPayload::Buffer(buf)) => Some(SignedRequestPayload::Buffer(buf.clone())),
+        Some(SignedRequestPayload::Stream(_)) => return None,
+        None => None,
+    };
+
+    Some(SignedRequest {
+        method: request.method.clone(),
+        service: request.service.clone(),
+        region: request.region.clone(),
+        path: request.path.clone(),
+        headers: request.headers.clone(),
+        params: request.params.clone(),
+        scheme: request.scheme.clone(),
+        hostname: request.hostname.clone(),
+        payload,
+        canonical_query_string: request.canonical_query_string.clone(),
+        canonical_uri: request.canonical_uri.clone(),
+    })
+}
+
+/// Percent-encode the bytes that may not appear in a URI path.
+///
+/// rusoto normally hands out an already encoded path, so `%` and the other
+/// path characters are left alone and the result still matches what was signed.
+fn encode_path(path: &str) -> String {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    let mut encoded = String::with_capacity(path.len());
+    for &b in path.as_bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/%!$&'()*+,;=:@".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push('%');
+            encoded.push(HEX[(b >> 4) as usize] as char);
+            encoded.push(HEX[(b & 0xf) as usize] as char);
         }
     }
+    encoded
+}
+
+fn prefix_path(base_path: &str, path: &str) -> String {
+    let base_path = base_path.trim_matches('/');
+    if base_path.is_empty() {
+        return path.to_string();
+    }
+
+    let path = if path.starts_with('/') {
+        &path[1..]
+    } else {
+        path
+    };
+
+    format!("/{}/{}", base_path, path)
+}
+
+impl<T> HttpClient<T>
+where
+    T: HttpService<RusotoBody> + Clone + Send + 'static,
+    T::Future: Send + 'static,
+    T::ResponseBody: Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<io::Error>,
+    T::Error: Into<Box<dyn std::error::Error + Send + Sync>> + Send + 'static,
+{
+    /// Dispatch `request` like `DispatchSignedRequest::dispatch`, resolving to
+    /// the `http::Response` instead of rusoto's `HttpResponse`, e.g. to look at
+    /// the HTTP version or the extensions.
+    pub fn dispatch_http(
+        &self,
+        request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> future::DispatchHttp<T> {
+        future::DispatchHttp::new(self.dispatch(request, timeout))
+    }
 
-    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
-        Ok(Async::Ready(None))
+    /// Send an `http::Request` that needs no signing, e.g. one to a presigned
+    /// URL, with the timeouts and response handling of this client.
+    ///
+    /// The request is sent as it is, none of the headers or URI settings of
+    /// the client are applied.
+    pub fn dispatch_raw(&self, request: Request<RusotoBody>) -> future::Dispatch<T> {
+        #[cfg(feature = "metrics")]
+        {
+            if let Some(metrics) = &self.config.metrics {
+                metrics.dispatched(None);
+            }
+        }
+
+        future::Dispatch::new(
+            self.client.clone(),
+            request,
+            self.config.timeout,
+            self.config.clone(),
+            &self.drain,
+        )
     }
 }
 
-impl From<Option<SignedRequestPayload>> for RusotoBody {
-    fn from(inner: Option<SignedRequestPayload>) -> Self {
-        RusotoBody { inner }
+impl<T: fmt::Debug> fmt::Debug for HttpClient<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpClient")
+            .field("client", &self.client)
+            .finish()
     }
 }
 
-impl<T> Stream for BodyStream<T>
+impl<T> DispatchSignedRequest for HttpClient<T>
 where
-    T: BufStream,
-    T::Error: Into<io::Error>,
+    T: HttpService<RusotoBody> + Clone + Send + 'static,
+    T::Future: Send + 'static,
+    T::ResponseBody: Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<io::Error>,
+    T::Error: Into<Box<dyn std::error::Error + Send + Sync>> + Send + 'static,
 {
-    type Item = Vec<u8>;
-    type Error = io::Error;
+    type Future = future::Dispatch<T>;
 
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        match self.body.poll_buf().map_err(|e| e.into())? {
-            Async::Ready(Some(buf)) => {
-                let bytes = buf.collect::<Vec<u8>>();
-                Ok(Async::Ready(Some(bytes)))
+    fn dispatch(&self, request: SignedRequest, timeout: Option<Duration>) -> Self::Future {
+        #[cfg(feature = "tracing")]
+        let span = trace::span(&request);
+
+        #[cfg(feature = "debug-log")]
+        debug_log::signed(&request);
+
+        #[cfg(feature = "metrics")]
+        let tag = metrics::tag(&request);
+
+        #[cfg(feature = "metrics")]
+        {
+            if let Some(metrics) = &self.config.metrics {
+                metrics.dispatched(tag.as_ref().map(String::as_str));
             }
-            Async::Ready(None) => Ok(Async::Ready(None)),
-            Async::NotReady => Ok(Async::NotReady),
         }
+
+        let request = match self.config.build_request(request) {
+            Ok(request) => request,
+            Err(err) => {
+                let fut = future::Dispatch::error(err, self.config.clone());
+                #[cfg(feature = "metrics")]
+                let fut = fut.with_tag(tag);
+                return fut;
+            }
+        };
+
+        let timeout = if self.config.honor_dispatch_timeout {
+            timeout.or(self.config.timeout)
+        } else {
+            self.config.timeout
+        };
+
+        let fut = future::Dispatch::new(
+            self.client.clone(),
+            request,
+            timeout,
+            self.config.clone(),
+            &self.drain,
+        );
+
+        #[cfg(feature = "tracing")]
+        let fut = fut.with_span(span);
+
+        #[cfg(feature = "metrics")]
+        let fut = fut.with_tag(tag);
+
+        fut
+    }
+}
+
+impl<T> Service<SignedRequest> for HttpClient<T>
+where
+    T: HttpService<RusotoBody> + Clone + Send + 'static,
+    T::Future: Send + 'static,
+    T::ResponseBody: Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<io::Error>,
+    T::Error: Into<Box<dyn std::error::Error + Send + Sync>> + Send + 'static,
+{
+    type Response = HttpResponse;
+    type Error = HttpDispatchError;
+    type Future = <Self as DispatchSignedRequest>::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        // Every call is dispatched on its own clone of the inner service and
+        // the returned future waits for that clone to become ready.
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, request: SignedRequest) -> Self::Future {
+        self.dispatch(request, None)
+    }
+}
+
+impl<T> BodyStream<T> {
+    fn new(
+        body: T,
+        max_bytes: Option<usize>,
+        deadline: Option<Delay>,
+        progress: Option<Progress>,
+        on_timeout: Option<OnBodyTimeout>,
+    ) -> Self {
+        BodyStream {
+            body,
+            max_bytes,
+            received: 0,
+            deadline,
+            progress,
+            on_timeout,
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for BodyStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BodyStream")
+            .field("body", &self.body)
+            .field("max_bytes", &self.max_bytes)
+            .field("received", &self.received)
+            .field("deadline", &self.deadline)
+            .finish()
+    }
+}
+
+impl<T> Stream for BodyStream<T>
+where
+    T: BufStream,
+    T::Item: 'static,
+    T::Error: Into<io::Error>,
+{
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let received = self.received;
+        let polled = self
+            .body
+            .poll_buf()
+            .map_err(|e| error::body_interrupted(e.into(), received))?;
+        match polled {
+            Async::Ready(Some(buf)) => {
+                let bytes = into_bytes(buf);
+
+                self.received += bytes.len();
+                if let Some(progress) = &self.progress {
+                    progress(self.received as u64);
+                }
+                if let Some(max) = self.max_bytes {
+                    if self.received > max {
+                        let msg = format!("response body exceeded the limit of {} bytes", max);
+                        return Err(error::body(io::Error::new(io::ErrorKind::Other, msg)));
+                    }
+                }
+
+                Ok(Async::Ready(Some(bytes)))
+            }
+            Async::Ready(None) => Ok(Async::Ready(None)),
+            Async::NotReady => {
+                if let Some(deadline) = &mut self.deadline {
+                    let elapsed = deadline
+                        .poll()
+                        .map_err(|e| error::body(io::Error::new(io::ErrorKind::Other, e)))?;
+                    if elapsed.is_ready() {
+                        if let Some(on_timeout) = &self.on_timeout {
+                            on_timeout();
+                        }
+                        let msg = "response body exceeded the total deadline";
+                        return Err(error::body(io::Error::new(io::ErrorKind::TimedOut, msg)));
+                    }
+                }
+
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+/// Render `uri` without its query, for messages that must not leak the
+/// signature of a presigned URL.
+pub(crate) fn without_query(uri: &Uri) -> String {
+    match (uri.scheme_part(), uri.authority_part()) {
+        (Some(scheme), Some(authority)) => format!("{}://{}{}", scheme, authority, uri.path()),
+        _ => uri.path().to_string(),
+    }
+}
+
+/// Turn a response body chunk into the `Bytes` handed to rusoto.
+///
+/// Chunks that already are `Bytes`, a cursor over them or a hyper `Chunk`
+/// are handed on as they are, anything else is copied once.
+fn into_bytes<B: Buf + 'static>(buf: B) -> Bytes {
+    let mut buf = Some(buf);
+    let any = &mut buf as &mut dyn Any;
+
+    if let Some(buf) = any.downcast_mut::<Option<Bytes>>() {
+        return buf.take().expect("chunk taken twice");
+    }
+    if let Some(buf) = any.downcast_mut::<Option<io::Cursor<Bytes>>>() {
+        let buf = buf.take().expect("chunk taken twice");
+        let position = buf.position() as usize;
+        return buf.into_inner().slice_from(position);
+    }
+    #[cfg(feature = "hyper")]
+    {
+        if let Some(buf) = any.downcast_mut::<Option<::hyper::Chunk>>() {
+            return buf.take().expect("chunk taken twice").into_bytes();
+        }
+    }
+
+    buf.take().expect("chunk taken twice").collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockBody, MockService};
+    use futures::{future, sync::mpsc, Future};
+    use http::{Response, StatusCode};
+    use rusoto_core::{credential::AwsCredentials, ByteStream, Region};
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    };
+    use tokio::runtime::current_thread::Runtime;
+    use tower_http::BodyExt;
+
+    fn signed_request(method: &str) -> SignedRequest {
+        let region = Region::Custom {
+            name: "us-east-1".to_string(),
+            endpoint: "http://localhost:4566".to_string(),
+        };
+        SignedRequest::new(method, "s3", &region, "/bucket/key")
+    }
+
+    #[test]
+    fn patch_is_sent_as_patch() {
+        let mock = MockService::new();
+        let client = HttpClient::new(mock.clone());
+
+        client
+            .dispatch(signed_request("PATCH"), None)
+            .wait()
+            .unwrap();
+
+        let requests = mock.take_requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method(), Method::PATCH);
+    }
+
+    /// Dispatch `request` on a client over a `MockService`, expecting it to
+    /// fail before the mock is called.
+    fn build_error(request: SignedRequest) -> String {
+        let mock = MockService::new();
+        let err = HttpClient::new(mock.clone())
+            .dispatch(request, None)
+            .wait()
+            .unwrap_err();
+        assert!(mock.take_requests().is_empty());
+        err.to_string()
+    }
+
+    #[test]
+    fn unknown_method_is_an_error() {
+        let err = build_error(signed_request("FROBNICATE"));
+        assert!(err.contains("unsupported method: FROBNICATE"), "{}", err);
+    }
+
+    #[test]
+    fn invalid_header_name_is_an_error() {
+        let mut request = signed_request("GET");
+        request.add_header("bad name", "value");
+
+        let err = build_error(request);
+        assert!(err.contains("invalid header name \"bad name\""), "{}", err);
+    }
+
+    #[test]
+    fn invalid_header_value_is_an_error() {
+        let mut request = signed_request("GET");
+        request.add_header("x-amz-meta-note", "line\nbreak");
+
+        let err = build_error(request);
+        assert!(err.contains("for header x-amz-meta-note"), "{}", err);
+    }
+
+    #[test]
+    fn broken_hostname_is_an_error() {
+        let mut request = signed_request("GET");
+        request.set_hostname(Some("not a host".to_string()));
+
+        let err = build_error(request);
+        assert!(err.starts_with("RequestBuildingError"), "{}", err);
+    }
+
+    /// A client over a `MockService` that answers with `response`.
+    fn respond_with(response: Response<MockBody>) -> (MockService, HttpClient<MockService>) {
+        let mock = MockService::new();
+        mock.respond_with(response);
+        (mock.clone(), HttpClient::new(mock))
+    }
+
+    fn non_utf8_response() -> Response<MockBody> {
+        Response::builder()
+            .header("x-amz-meta-raw", HeaderValue::from_bytes(&[0xFF]).unwrap())
+            .body(MockBody::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn non_utf8_response_header_is_decoded_lossily() {
+        let (_, client) = respond_with(non_utf8_response());
+
+        let response = client.dispatch(signed_request("GET"), None).wait().unwrap();
+        assert_eq!(response.headers.get("x-amz-meta-raw"), Some("\u{FFFD}"));
+    }
+
+    /// Never responds.
+    #[derive(Clone)]
+    struct Never;
+
+    impl Service<Request<RusotoBody>> for Never {
+        type Response = Response<MockBody>;
+        type Error = io::Error;
+        type Future = future::Empty<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: Request<RusotoBody>) -> Self::Future {
+            future::empty()
+        }
+    }
+
+    #[test]
+    fn dispatch_timeout_fails_a_hanging_request() {
+        let client = HttpClient::new(Never);
+        let fut = client.dispatch(signed_request("GET"), Some(Duration::from_millis(10)));
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(fut).unwrap_err().to_string();
+        assert!(err.ends_with("request timed out"), "{}", err);
+    }
+
+    /// Dispatch `request` on a client built by `build` over a `MockService`,
+    /// returning the request the mock got.
+    fn sent_request<F>(build: F, request: SignedRequest) -> Request<RusotoBody>
+    where
+        F: FnOnce(HttpClientBuilder<MockService>) -> HttpClientBuilder<MockService>,
+    {
+        let mock = MockService::new();
+        let client = build(HttpClient::builder(mock.clone())).build();
+        client.dispatch(request, None).wait().unwrap();
+
+        let mut requests = mock.take_requests();
+        assert_eq!(requests.len(), 1);
+        requests.remove(0)
+    }
+
+    #[test]
+    fn user_agent_is_set() {
+        let request = sent_request(|b| b, signed_request("GET"));
+        assert_eq!(request.headers()[USER_AGENT], DEFAULT_USER_AGENT);
+
+        let custom = HeaderValue::from_static("my-app/1.0");
+        let request = sent_request(|b| b.user_agent(custom), signed_request("GET"));
+        assert_eq!(request.headers()[USER_AGENT], "my-app/1.0");
+
+        let request = sent_request(|b| b.no_user_agent(), signed_request("GET"));
+        assert!(!request.headers().contains_key(USER_AGENT));
+    }
+
+    #[test]
+    fn builder_setters_chain() {
+        let request = sent_request(
+            |b| {
+                b.no_user_agent()
+                    .user_agent(HeaderValue::from_static("my-app/1.0"))
+            },
+            signed_request("GET"),
+        );
+
+        assert_eq!(request.headers()[USER_AGENT], "my-app/1.0");
+    }
+
+    #[test]
+    fn inner_service_round_trips() {
+        let mut client = HttpClient::new(vec![1, 2]);
+        assert_eq!(client.get_ref(), &[1, 2]);
+        client.get_mut().push(3);
+        assert_eq!(client.into_inner(), vec![1, 2, 3]);
+    }
+
+    /// Read the whole body of a request the mock got, chunk by chunk.
+    fn body_chunks(request: Request<RusotoBody>) -> Vec<Bytes> {
+        let mut body = request.into_body();
+        futures::stream::poll_fn(move || body.poll_buf())
+            .map(io::Cursor::into_inner)
+            .collect()
+            .wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn large_buffer_is_sent_in_chunks() {
+        let payload = vec![7; 200 * 1024];
+        let mut request = signed_request("PUT");
+        request.set_payload(Some(payload.clone()));
+
+        let chunks = body_chunks(sent_request(|b| b, request));
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 64 * 1024));
+        assert_eq!(chunks.concat(), payload);
+    }
+
+    #[test]
+    fn dispatches_through_a_service_builder_stack() {
+        let mock = MockService::new();
+        let mut service = tower::ServiceBuilder::new()
+            .concurrency_limit(1)
+            .service(HttpClient::new(mock.clone()));
+
+        future::poll_fn(|| service.poll_ready()).wait().unwrap();
+        let response = service.call(signed_request("GET")).wait().unwrap();
+
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(mock.take_requests().len(), 1);
+    }
+
+    #[test]
+    fn dispatch_resolves_to_the_response() {
+        let response = Response::builder()
+            .status(StatusCode::CREATED)
+            .body(MockBody::from_chunks(vec!["created"]))
+            .unwrap();
+        let (_, client) = respond_with(response);
+
+        let response = client.dispatch(signed_request("PUT"), None).wait().unwrap();
+        assert_eq!(response.status, StatusCode::CREATED);
+        let body = response.body.concat2().wait().unwrap();
+        assert_eq!(&body[..], b"created");
+    }
+
+    #[test]
+    fn base_path_is_prefixed() {
+        for base_path in &["minio", "/minio", "minio/", "/minio/"] {
+            assert_eq!(prefix_path(base_path, "/bucket/key"), "/minio/bucket/key");
+        }
+        assert_eq!(prefix_path("/", "/bucket/key"), "/bucket/key");
+
+        let request = sent_request(|b| b.base_path("/minio/"), signed_request("GET"));
+        assert_eq!(request.uri().path(), "/minio/bucket/key");
+    }
+
+    #[test]
+    fn force_scheme_overrides_the_signed_scheme() {
+        let mut request = signed_request("GET");
+        request.scheme = Some("https".to_string());
+
+        let request = sent_request(|b| b.force_scheme(Scheme::HTTP), request);
+        assert!(
+            request.uri().to_string().starts_with("http://"),
+            "{}",
+            request.uri()
+        );
+    }
+
+    #[test]
+    fn content_length_becomes_the_size_hint() {
+        let response = Response::builder()
+            .header("content-length", "5")
+            .body(MockBody::from_chunks(vec!["hello"]))
+            .unwrap();
+        let (_, client) = respond_with(response);
+
+        let response = client.dispatch(signed_request("GET"), None).wait().unwrap();
+        assert_eq!(response.body.size_hint(), Some(5));
+
+        let (_, client) = respond_with(Response::new(MockBody::from_chunks(vec!["hello"])));
+        let response = client.dispatch(signed_request("GET"), None).wait().unwrap();
+        assert_eq!(response.body.size_hint(), None);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_response_is_decompressed() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"hello gzip").unwrap();
+        let compressed = encoder.finish().unwrap();
+        let response = Response::builder()
+            .header("content-encoding", "gzip")
+            .header("content-length", compressed.len().to_string().as_str())
+            .body(MockBody::from(compressed))
+            .unwrap();
+        let mock = MockService::new();
+        mock.respond_with(response);
+        let client = HttpClient::builder(mock).gzip(true).build();
+
+        let response = client.dispatch(signed_request("GET"), None).wait().unwrap();
+        assert_eq!(response.headers.get("content-encoding"), None);
+        assert_eq!(response.headers.get("content-length"), None);
+        let body = response.body.concat2().wait().unwrap();
+        assert_eq!(&body[..], b"hello gzip");
+    }
+
+    #[test]
+    fn response_past_the_limit_fails_the_body() {
+        // The advertised length is within the limit, the delivered body isn't.
+        let response = Response::builder()
+            .header("content-length", "4")
+            .body(MockBody::from_chunks(vec!["abc", "def"]))
+            .unwrap();
+        let mock = MockService::new();
+        mock.respond_with(response);
+        let client = HttpClient::builder(mock).max_response_bytes(4).build();
+
+        let response = client.dispatch(signed_request("GET"), None).wait().unwrap();
+        let mut body = response.body.wait();
+        assert_eq!(body.next().unwrap().unwrap(), Bytes::from("abc"));
+        let err = body.next().unwrap().unwrap_err();
+        assert!(
+            err.to_string().contains("exceeded the limit of 4 bytes"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn default_headers_are_added_without_overriding_signed_ones() {
+        let mut request = signed_request("GET");
+        request.add_header("x-corp-team", "signed");
+
+        let request = sent_request(
+            |b| {
+                b.default_header(
+                    HeaderName::from_static("x-corp-tenant"),
+                    HeaderValue::from_static("acme"),
+                )
+                .default_header(
+                    HeaderName::from_static("x-corp-team"),
+                    HeaderValue::from_static("default"),
+                )
+            },
+            request,
+        );
+
+        assert_eq!(request.headers()["x-corp-tenant"], "acme");
+        let teams: Vec<_> = request.headers().get_all("x-corp-team").iter().collect();
+        assert_eq!(teams, vec!["signed"]);
+    }
+
+    #[test]
+    fn repeated_response_headers_are_folded() {
+        let response = Response::builder()
+            .header("x-amz-meta-tag", "a")
+            .header("x-amz-meta-tag", "b")
+            .body(MockBody::empty())
+            .unwrap();
+        let (_, client) = respond_with(response);
+
+        let response = client.dispatch(signed_request("GET"), None).wait().unwrap();
+        assert_eq!(response.headers.get("x-amz-meta-tag"), Some("a, b"));
+    }
+
+    #[test]
+    fn host_override_sets_the_host_header() {
+        let request = sent_request(
+            |b| b.host_override(|_| Some(HeaderValue::from_static("bucket.s3.amazonaws.com"))),
+            signed_request("GET"),
+        );
+
+        assert_eq!(request.headers()[HOST], "bucket.s3.amazonaws.com");
+        assert_eq!(request.uri().host(), Some("localhost"));
+    }
+
+    /// Responds right away with the body of the request, so the response can
+    /// only be read as fast as the request body is produced.
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<Request<RusotoBody>> for Echo {
+        type Response = Response<RusotoBody>;
+        type Error = io::Error;
+        type Future = future::FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, request: Request<RusotoBody>) -> Self::Future {
+            future::ok(Response::new(request.into_body()))
+        }
+    }
+
+    /// A streaming payload fed through the returned sender.
+    fn streaming_payload() -> (mpsc::UnboundedSender<Bytes>, SignedRequestPayload) {
+        let (tx, rx) = mpsc::unbounded();
+        let rx = rx.map_err(|()| io::Error::new(io::ErrorKind::Other, "sender failed"));
+        (tx, SignedRequestPayload::Stream(ByteStream::new(rx)))
+    }
+
+    #[test]
+    fn total_deadline_bounds_the_response_body() {
+        let deadlines = DeadlineConfig {
+            response_head: None,
+            total: Some(Duration::from_millis(20)),
+        };
+        let client = HttpClient::builder(Echo).deadlines(deadlines).build();
+        let (tx, payload) = streaming_payload();
+        let mut request = signed_request("PUT");
+        request.payload = Some(payload);
+
+        // The head is in right away, then the body stalls after one chunk.
+        tx.unbounded_send(Bytes::from("first")).unwrap();
+        let mut rt = Runtime::new().unwrap();
+        let response = rt.block_on(client.dispatch(request, None)).unwrap();
+        let err = rt.block_on(response.body.concat2()).unwrap_err();
+        drop(tx);
+
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(err.to_string().contains("total deadline"), "{}", err);
+    }
+
+    #[test]
+    fn clones_dispatch_through_one_shared_handle() {
+        // `MockService` is a handle to shared state, as a connection pool would be.
+        let mock = MockService::new();
+        let client = HttpClient::new(mock.clone());
+        let other = client.clone();
+
+        client.dispatch(signed_request("GET"), None).wait().unwrap();
+        other.dispatch(signed_request("PUT"), None).wait().unwrap();
+
+        let methods: Vec<_> = mock
+            .take_requests()
+            .iter()
+            .map(|r| r.method().clone())
+            .collect();
+        assert_eq!(methods, vec![Method::GET, Method::PUT]);
+    }
+
+    #[test]
+    fn query_is_sent_as_it_was_signed() {
+        let mut request = signed_request("GET");
+        request.add_param("prefix", "a b+c");
+        request.add_param("marker", "x%20y");
+        request.sign(&AwsCredentials::new("AKID", "secret", None, None));
+        let signed = request.canonical_query_string().to_string();
+        assert!(signed.contains("a%20b%2Bc"), "{}", signed);
+
+        let request = sent_request(|b| b, request);
+        assert_eq!(request.uri().query(), Some(&signed[..]));
+    }
+
+    #[test]
+    fn bodyless_put_gets_a_zero_content_length() {
+        let request = sent_request(|b| b, signed_request("PUT"));
+        assert_eq!(request.headers()[CONTENT_LENGTH], "0");
+
+        let request = sent_request(|b| b, signed_request("GET"));
+        assert!(!request.headers().contains_key(CONTENT_LENGTH));
+    }
+
+    #[test]
+    fn dispatch_timeout_is_accepted() {
+        let (_, client) = respond_with(Response::new(MockBody::empty()));
+
+        let fut = client.dispatch(signed_request("GET"), Some(Duration::from_secs(5)));
+        let response = Runtime::new().unwrap().block_on(fut).unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+    }
+
+    #[test]
+    fn client_timeout_applies_when_dispatch_has_none() {
+        let mut rt = Runtime::new().unwrap();
+
+        let client = HttpClient::builder(Never)
+            .timeout(Duration::from_millis(10))
+            .build();
+        let err = rt
+            .block_on(client.dispatch(signed_request("GET"), None))
+            .unwrap_err()
+            .to_string();
+        assert!(err.ends_with("request timed out"), "{}", err);
+
+        // Not honoring the dispatch timeout keeps the short client timeout.
+        let client = HttpClient::builder(Never)
+            .timeout(Duration::from_millis(10))
+            .honor_dispatch_timeout(false)
+            .build();
+        let fut = client.dispatch(signed_request("GET"), Some(Duration::from_secs(60)));
+        let err = rt.block_on(fut).unwrap_err().to_string();
+        assert!(err.ends_with("request timed out"), "{}", err);
+    }
+
+    /// Fails every call as if the connection had been refused.
+    #[derive(Clone)]
+    struct Refused;
+
+    impl Service<Request<RusotoBody>> for Refused {
+        type Response = Response<MockBody>;
+        type Error = io::Error;
+        type Future = future::FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: Request<RusotoBody>) -> Self::Future {
+            future::err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "connection refused",
+            ))
+        }
+    }
+
+    #[test]
+    fn transport_error_names_the_request() {
+        let err = HttpClient::new(Refused)
+            .dispatch(signed_request("GET"), None)
+            .wait()
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "ConnectError: GET http://localhost:4566/bucket/key: connection refused"
+        );
+    }
+
+    struct Noop;
+
+    impl futures::executor::Notify for Noop {
+        fn notify(&self, _: usize) {}
+    }
+
+    /// Poll `fut` once from a task that is never woken up.
+    fn poll_once<F: Future>(fut: F) -> (Poll<F::Item, F::Error>, F) {
+        let mut task = futures::executor::spawn(fut);
+        let polled = task.poll_future_notify(&Arc::new(Noop), 0);
+        (polled, task.into_inner())
+    }
+
+    /// Holds on to every request it is called with and never responds.
+    #[derive(Clone)]
+    struct Hold;
+
+    impl Service<Request<RusotoBody>> for Hold {
+        type Response = Response<MockBody>;
+        type Error = io::Error;
+        type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error> + Send>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, request: Request<RusotoBody>) -> Self::Future {
+            Box::new(future::poll_fn(move || {
+                let _ = &request;
+                Ok(Async::NotReady)
+            }))
+        }
+    }
+
+    #[test]
+    fn dropping_the_future_releases_the_request_body() {
+        let client = HttpClient::new(Hold);
+        let (tx, payload) = streaming_payload();
+        let mut request = signed_request("PUT");
+        request.payload = Some(payload);
+
+        let (polled, fut) = poll_once(client.dispatch(request, None));
+        assert!(polled.unwrap().is_not_ready());
+        tx.unbounded_send(Bytes::from("partial")).unwrap();
+        drop(fut);
+
+        // The body the inner service was still holding is gone.
+        assert!(tx.unbounded_send(Bytes::from("more")).is_err());
+    }
+
+    #[test]
+    fn response_header_filter_drops_headers() {
+        let response = Response::builder()
+            .header("connection", "keep-alive")
+            .header("x-amz-request-id", "abc")
+            .body(MockBody::empty())
+            .unwrap();
+        let mock = MockService::new();
+        mock.respond_with(response);
+        let client = HttpClient::builder(mock)
+            .response_header_filter(|name| name != http::header::CONNECTION)
+            .build();
+
+        let response = client.dispatch(signed_request("GET"), None).wait().unwrap();
+        assert_eq!(response.headers.get("connection"), None);
+        assert_eq!(response.headers.get("x-amz-request-id"), Some("abc"));
+    }
+
+    #[test]
+    fn unsafe_key_characters_are_encoded() {
+        let region = Region::Custom {
+            name: "us-east-1".to_string(),
+            endpoint: "http://localhost:4566".to_string(),
+        };
+        let request = SignedRequest::new("GET", "s3", &region, "/bucket/my key#1");
+        let signed = request.canonical_path();
+
+        let request = sent_request(|b| b, request);
+        assert_eq!(request.uri().path(), "/bucket/my%20key%231");
+        assert_eq!(request.uri().path(), signed);
+        assert_eq!(request.uri().query(), None);
+    }
+
+    #[test]
+    fn strict_body_rejects_an_empty_put() {
+        let mut request = signed_request("PUT");
+        request.set_payload(Some(Vec::new()));
+        let mock = MockService::new();
+        let client = HttpClient::builder(mock.clone()).strict_body(true).build();
+
+        let err = client
+            .dispatch(request, None)
+            .wait()
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("RequestBuildingError"), "{}", err);
+        assert!(err.ends_with("request body is empty"), "{}", err);
+        assert!(mock.take_requests().is_empty());
+
+        // GETs have no body to check.
+        client.dispatch(signed_request("GET"), None).wait().unwrap();
+    }
+
+    /// Only ready once `open` is set, then forwards to a `MockService`.
+    #[derive(Clone)]
+    struct Gate {
+        open: Arc<AtomicBool>,
+        mock: MockService,
+    }
+
+    impl Service<Request<RusotoBody>> for Gate {
+        type Response = Response<MockBody>;
+        type Error = io::Error;
+        type Future = future::FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            if self.open.load(Ordering::SeqCst) {
+                Ok(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        }
+
+        fn call(&mut self, request: Request<RusotoBody>) -> Self::Future {
+            assert!(self.open.load(Ordering::SeqCst), "called before ready");
+            self.mock.call(request)
+        }
+    }
+
+    #[test]
+    fn dispatch_waits_for_the_service_to_be_ready() {
+        let open = Arc::new(AtomicBool::new(false));
+        let mock = MockService::new();
+        let client = HttpClient::new(Gate {
+            open: open.clone(),
+            mock: mock.clone(),
+        });
+
+        let (polled, fut) = poll_once(client.dispatch(signed_request("GET"), None));
+        assert!(polled.unwrap().is_not_ready());
+        assert!(mock.take_requests().is_empty());
+
+        open.store(true, Ordering::SeqCst);
+        let (polled, _) = poll_once(fut);
+        assert!(polled.unwrap().is_ready());
+        assert_eq!(mock.take_requests().len(), 1);
+    }
+
+    #[test]
+    fn expect_continue_is_sent_without_reading_the_payload() {
+        let polls = Arc::new(AtomicUsize::new(0));
+        let counted = polls.clone();
+        let mut sent = false;
+        let stream = futures::stream::poll_fn(move || {
+            counted.fetch_add(1, Ordering::SeqCst);
+            let chunk = if sent {
+                None
+            } else {
+                Some(Bytes::from("payload"))
+            };
+            sent = true;
+            Ok::<_, io::Error>(Async::Ready(chunk))
+        });
+
+        let mock = MockService::new();
+        let client = HttpClient::new(mock.clone());
+        let mut request = signed_request("PUT");
+        request.add_header("Expect", "100-continue");
+        request.payload = Some(SignedRequestPayload::Stream(ByteStream::new(stream)));
+        client.dispatch(request, None).wait().unwrap();
+
+        let mut requests = mock.take_requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].headers()["expect"], "100-continue");
+        assert_eq!(polls.load(Ordering::SeqCst), 0);
+
+        // The payload is only read once the inner service polls the body.
+        let chunk = requests[0].body_mut().poll_buf().unwrap();
+        assert!(chunk.is_ready());
+        assert_eq!(polls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn errors_are_categorized() {
+        let err = HttpClient::new(Refused)
+            .dispatch(signed_request("GET"), None)
+            .wait()
+            .unwrap_err();
+        assert!(err.to_string().starts_with("ConnectError: "), "{}", err);
+
+        let chunks = vec![
+            Ok(Bytes::from("partial")),
+            Err(io::Error::new(io::ErrorKind::Other, "disk on fire")),
+        ];
+        let mut request = signed_request("PUT");
+        request.payload = Some(SignedRequestPayload::Stream(ByteStream::new(
+            futures::stream::iter_result(chunks),
+        )));
+        let response = HttpClient::new(Echo)
+            .dispatch(request, None)
+            .wait()
+            .unwrap();
+        let err = response.body.concat2().wait().unwrap_err().to_string();
+        assert!(err.starts_with("BodyError: "), "{}", err);
+        assert!(
+            err.ends_with("disk on fire (after 7 bytes of the body)"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn request_id_is_sent_and_named_in_errors() {
+        let request = sent_request(
+            |b| b.request_id(|| HeaderValue::from_static("req-1")),
+            signed_request("GET"),
+        );
+        assert_eq!(request.headers()[X_REQUEST_ID], "req-1");
+
+        // An id the signed request already carries is kept.
+        let mut signed = signed_request("GET");
+        signed.add_header(X_REQUEST_ID, "signed-id");
+        let request = sent_request(
+            |b| b.request_id(|| HeaderValue::from_static("req-1")),
+            signed,
+        );
+        assert_eq!(request.headers()[X_REQUEST_ID], "signed-id");
+
+        let err = HttpClient::builder(Refused)
+            .request_id(|| HeaderValue::from_static("req-2"))
+            .build()
+            .dispatch(signed_request("GET"), None)
+            .wait()
+            .unwrap_err();
+        assert!(err.to_string().contains("(request id req-2)"), "{}", err);
+    }
+
+    #[test]
+    fn debug_output_summarizes() {
+        let client = HttpClient::new(vec![1]);
+        assert_eq!(format!("{:?}", client), "HttpClient { client: [1] }");
+
+        let body = RusotoBody::from(Some(SignedRequestPayload::Buffer(Bytes::from("hello"))));
+        let body = format!("{:?}", body);
+        assert!(
+            body.contains("Buffer") && body.contains("len: 5"),
+            "{}",
+            body
+        );
+
+        let stream = BodyStream::new(MockBody::empty(), Some(10), None, None, None);
+        let stream = format!("{:?}", stream);
+        assert!(stream.starts_with("BodyStream {"), "{}", stream);
+        assert!(stream.contains("max_bytes: Some(10)"), "{}", stream);
+    }
+
+    /// A response body of one chunk followed by a checksum trailer.
+    #[derive(Debug, Default)]
+    struct WithTrailer {
+        data_done: bool,
+    }
+
+    impl Body for WithTrailer {
+        type Item = io::Cursor<Bytes>;
+        type Error = io::Error;
+
+        fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+            if self.data_done {
+                return Ok(Async::Ready(None));
+            }
+            self.data_done = true;
+            Ok(Async::Ready(Some(io::Cursor::new(Bytes::from("data")))))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("x-amz-checksum-crc32", HeaderValue::from_static("abc"));
+            Ok(Async::Ready(Some(trailers)))
+        }
+    }
+
+    #[derive(Clone)]
+    struct Trailing;
+
+    impl Service<Request<RusotoBody>> for Trailing {
+        type Response = Response<WithTrailer>;
+        type Error = io::Error;
+        type Future = future::FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: Request<RusotoBody>) -> Self::Future {
+            future::ok(Response::new(WithTrailer::default()))
+        }
+    }
+
+    #[test]
+    fn response_trailers_are_surfaced_as_headers() {
+        let client = HttpClient::builder(Trailing)
+            .response_trailers(true)
+            .build();
+        let response = client.dispatch(signed_request("GET"), None).wait().unwrap();
+        assert_eq!(response.headers.get("x-amz-checksum-crc32"), Some("abc"));
+        let body = response.body.concat2().wait().unwrap();
+        assert_eq!(&body[..], b"data");
+
+        let response = HttpClient::new(Trailing)
+            .dispatch(signed_request("GET"), None)
+            .wait()
+            .unwrap();
+        assert_eq!(response.headers.get("x-amz-checksum-crc32"), None);
+    }
+
+    #[test]
+    fn connect_is_rejected() {
+        let err = build_error(signed_request("CONNECT"));
+        assert!(err.starts_with("RequestBuildingError"), "{}", err);
+        assert!(err.contains("CONNECT is not supported"), "{}", err);
+    }
+
+    /// Sends each request to the next of its backends in turn. Like a
+    /// balancer, a clone would keep its own count of where to send the next.
+    struct RoundRobin {
+        backends: Vec<MockService>,
+        next: usize,
+    }
+
+    impl Service<Request<RusotoBody>> for RoundRobin {
+        type Response = Response<MockBody>;
+        type Error = io::Error;
+        type Future = future::FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, request: Request<RusotoBody>) -> Self::Future {
+            let backend = self.next % self.backends.len();
+            self.next += 1;
+            self.backends[backend].call(request)
+        }
+    }
+
+    #[test]
+    fn requests_are_spread_over_a_balancer() {
+        let backends = vec![MockService::new(), MockService::new()];
+        let balancer = RoundRobin {
+            backends: backends.clone(),
+            next: 0,
+        };
+
+        // The buffer worker is spawned onto the runtime it is created on.
+        let mut rt = Runtime::new().unwrap();
+        let buffer = rt
+            .block_on(future::lazy(move || {
+                tower::buffer::Buffer::new(balancer, 4)
+            }))
+            .unwrap_or_else(|_| panic!("failed to spawn the buffer worker"));
+        let client = HttpClient::new(buffer);
+
+        let dispatches = (0..4).map(|_| client.dispatch(signed_request("GET"), None));
+        rt.block_on(future::join_all(dispatches)).unwrap();
+
+        assert_eq!(backends[0].take_requests().len(), 2);
+        assert_eq!(backends[1].take_requests().len(), 2);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn accept_encoding_is_only_added_when_asked_for() {
+        let request = sent_request(|b| b.accept_gzip(true), signed_request("GET"));
+        assert_eq!(request.headers()[ACCEPT_ENCODING], "gzip");
+
+        // A signed value is left as it is.
+        let mut signed = signed_request("GET");
+        signed.add_header("accept-encoding", "identity");
+        let request = sent_request(|b| b.accept_gzip(true), signed);
+        assert_eq!(request.headers()[ACCEPT_ENCODING], "identity");
+
+        let request = sent_request(|b| b.gzip(true), signed_request("GET"));
+        assert!(!request.headers().contains_key(ACCEPT_ENCODING));
+    }
+
+    /// A progress callback and the values it was called with.
+    fn progress_recorder() -> (Arc<Mutex<Vec<u64>>>, impl Fn(u64) + Send + Sync + 'static) {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+        (seen, move |n| recorded.lock().unwrap().push(n))
+    }
+
+    #[test]
+    fn progress_adds_up_to_the_body_size() {
+        let (uploaded, on_upload) = progress_recorder();
+        let mut request = signed_request("PUT");
+        request.set_payload(Some(vec![1; 100 * 1024]));
+        let request = sent_request(
+            |b| b.max_chunk_size(64 * 1024).on_upload_progress(on_upload),
+            request,
+        );
+        body_chunks(request);
+        assert_eq!(*uploaded.lock().unwrap(), vec![64 * 1024, 100 * 1024]);
+
+        let (downloaded, on_download) = progress_recorder();
+        let mock = MockService::new();
+        mock.respond_with(Response::new(MockBody::from_chunks(vec!["abc", "defg"])));
+        let client = HttpClient::builder(mock)
+            .on_download_progress(on_download)
+            .build();
+        let response = client.dispatch(signed_request("GET"), None).wait().unwrap();
+        response.body.concat2().wait().unwrap();
+        assert_eq!(*downloaded.lock().unwrap(), vec![3, 7]);
+    }
+
+    #[test]
+    fn ipv6_hostname_is_bracketed() {
+        let mut request = signed_request("GET");
+        request.set_hostname(Some("::1".to_string()));
+
+        let request = sent_request(|b| b, request);
+        assert_eq!(request.uri(), "http://[::1]/bucket/key");
+    }
+
+    #[test]
+    fn port_ends_up_in_the_uri() {
+        // The port of a custom endpoint is kept as it is.
+        let request = sent_request(|b| b, signed_request("GET"));
+        assert_eq!(request.uri(), "http://localhost:4566/bucket/key");
+
+        let request = sent_request(|b| b.port(9000), signed_request("GET"));
+        assert_eq!(request.uri(), "http://localhost:9000/bucket/key");
+    }
+
+    #[test]
+    fn header_decode_policies() {
+        let dispatch = |policy| {
+            let mock = MockService::new();
+            mock.respond_with(non_utf8_response());
+            HttpClient::builder(mock)
+                .header_decode_policy(policy)
+                .build()
+                .dispatch(signed_request("GET"), None)
+                .wait()
+        };
+
+        let response = dispatch(HeaderDecodePolicy::Lossy).unwrap();
+        assert_eq!(response.headers.get("x-amz-meta-raw"), Some("\u{FFFD}"));
+
+        let response = dispatch(HeaderDecodePolicy::Skip).unwrap();
+        assert_eq!(response.headers.get("x-amz-meta-raw"), None);
+
+        let err = dispatch(HeaderDecodePolicy::Error).unwrap_err().to_string();
+        assert!(err.starts_with("ResponseMappingError"), "{}", err);
+        assert!(
+            err.ends_with("response header x-amz-meta-raw is not valid UTF-8"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn payload_stream_bytes_pass_through_unchanged() {
+        let first = Bytes::from(vec![1; 1024]);
+        let second = Bytes::from(vec![2; 1024]);
+        let stream = futures::stream::iter_ok::<_, io::Error>(vec![first.clone(), second.clone()]);
+        let mut request = signed_request("PUT");
+        request.payload = Some(SignedRequestPayload::Stream(ByteStream::new(stream)));
+
+        let chunks = body_chunks(sent_request(|b| b, request));
+        assert_eq!(chunks, vec![first.clone(), second.clone()]);
+        assert_eq!(chunks[0].as_ptr(), first.as_ptr());
+        assert_eq!(chunks[1].as_ptr(), second.as_ptr());
+    }
+
+    #[test]
+    fn dispatch_raw_sends_the_request_as_is() {
+        let response = Response::builder()
+            .status(StatusCode::OK)
+            .body(MockBody::from("presigned"))
+            .unwrap();
+        let (mock, client) = respond_with(response);
+
+        let uri = "https://bucket.s3.amazonaws.com/key?X-Amz-Signature=abc";
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(uri)
+            .body(RusotoBody::empty())
+            .unwrap();
+        let response = client.dispatch_raw(request).wait().unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(&response.body.concat2().wait().unwrap()[..], b"presigned");
+
+        let requests = mock.take_requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].uri(), uri);
+    }
+
+    #[test]
+    fn treat_5xx_as_error_fails_a_503() {
+        let unavailable = || {
+            Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(MockBody::empty())
+                .unwrap()
+        };
+
+        let mock = MockService::new();
+        mock.respond_with(unavailable());
+        let client = HttpClient::builder(mock).treat_5xx_as_error(true).build();
+        let err = client
+            .dispatch(signed_request("GET"), None)
+            .wait()
+            .unwrap_err();
+        let err = err.to_string();
+        assert!(err.starts_with("ServerStatusError"), "{}", err);
+        assert!(err.ends_with("server responded with 503 Service Unavailable"));
+
+        // Without the flag the status is left to rusoto.
+        let (_, client) = respond_with(unavailable());
+        let response = client.dispatch(signed_request("GET"), None).wait().unwrap();
+        assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn on_request_hook_sees_the_built_request() {
+        let request = sent_request(
+            |b| {
+                b.on_request(|request| {
+                    assert_eq!(request.uri().path(), "/bucket/key");
+                    let trace = HeaderValue::from_static("abc");
+                    request.headers_mut().insert("x-trace-id", trace);
+                })
+            },
+            signed_request("GET"),
+        );
+
+        assert_eq!(request.headers()["x-trace-id"], "abc");
+    }
+
+    #[test]
+    fn empty_hostname_is_an_error() {
+        let mut request = signed_request("GET");
+        request.set_hostname(Some(String::new()));
+
+        let err = build_error(request);
+        assert!(err.starts_with("RequestBuildingError"), "{}", err);
+        assert!(
+            err.ends_with(
+                "GET /bucket/key: the request has no hostname, check the region or endpoint"
+            ),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn default_content_type_is_only_added_to_bodies_without_one() {
+        let content_type = || HeaderValue::from_static("application/octet-stream");
+
+        let mut request = signed_request("PUT");
+        request.set_payload(Some(b"data".to_vec()));
+        let request = sent_request(|b| b.default_content_type(content_type()), request);
+        assert_eq!(request.headers()[CONTENT_TYPE], "application/octet-stream");
+
+        let mut request = signed_request("PUT");
+        request.set_payload(Some(b"{}".to_vec()));
+        request.add_header("content-type", "application/json");
+        let request = sent_request(|b| b.default_content_type(content_type()), request);
+        assert_eq!(request.headers()[CONTENT_TYPE], "application/json");
+
+        let request = sent_request(
+            |b| b.default_content_type(content_type()),
+            signed_request("GET"),
+        );
+        assert!(!request.headers().contains_key(CONTENT_TYPE));
+    }
+
+    /// Responds with an empty `200 OK` after `delay`.
+    #[derive(Clone)]
+    struct Delayed {
+        delay: Duration,
+    }
+
+    impl Service<Request<RusotoBody>> for Delayed {
+        type Response = Response<MockBody>;
+        type Error = io::Error;
+        type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error> + Send>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: Request<RusotoBody>) -> Self::Future {
+            let delay = Delay::new(std::time::Instant::now() + self.delay);
+            Box::new(
+                delay
+                    .map(|()| Response::new(MockBody::empty()))
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e)),
+            )
+        }
+    }
+
+    #[test]
+    fn on_complete_reports_the_latency() {
+        let latencies = Arc::new(Mutex::new(Vec::new()));
+        let recorded = latencies.clone();
+        let delay = Duration::from_millis(20);
+        let client = HttpClient::builder(Delayed { delay })
+            .on_complete(move |elapsed, result| {
+                assert!(result.is_ok());
+                recorded.lock().unwrap().push(elapsed);
+            })
+            .build();
+
+        let fut = client.dispatch(signed_request("GET"), None);
+        Runtime::new().unwrap().block_on(fut).unwrap();
+
+        let latencies = latencies.lock().unwrap();
+        assert_eq!(latencies.len(), 1);
+        assert!(latencies[0] >= delay, "{:?}", latencies[0]);
+        assert!(latencies[0] < Duration::from_secs(5), "{:?}", latencies[0]);
+    }
+
+    #[test]
+    fn empty_path_gets_a_slash_before_the_query() {
+        let region = Region::Custom {
+            name: "us-east-1".to_string(),
+            endpoint: "http://localhost:4566".to_string(),
+        };
+        let mut request = SignedRequest::new("GET", "s3", &region, "");
+        request.canonical_query_string = "list-type=2".to_string();
+
+        let request = sent_request(|b| b, request);
+        assert_eq!(request.uri(), "http://localhost:4566/?list-type=2");
+    }
+
+    #[test]
+    fn upload_and_download_chunks_interleave() {
+        let client = HttpClient::new(Echo);
+        let (tx, payload) = streaming_payload();
+        let mut request = signed_request("PUT");
+        request.payload = Some(payload);
+
+        // The head arrives before any of the request body has been produced.
+        let response = client.dispatch(request, None).wait().unwrap();
+        let mut body = response.body.wait();
+
+        for chunk in &["first", "second", "third"] {
+            tx.unbounded_send(Bytes::from(*chunk)).unwrap();
+            assert_eq!(body.next().unwrap().unwrap(), Bytes::from(*chunk));
+        }
+        drop(tx);
+        assert!(body.next().is_none());
+    }
+
+    #[test]
+    fn get_has_an_empty_body() {
+        let request = sent_request(|b| b, signed_request("GET"));
+        let body = request.body();
+
+        let hint = body.size_hint();
+        assert_eq!((hint.lower(), hint.upper()), (0, Some(0)));
+        assert!(body.is_end_stream());
+        assert!(body_chunks(request).is_empty());
+    }
+
+    #[test]
+    fn max_concurrency_serializes_dispatches() {
+        let client = HttpClient::builder(Echo)
+            .response_trailers(true)
+            .max_concurrency(1)
+            .build();
+        let (tx, payload) = streaming_payload();
+        let mut request = signed_request("PUT");
+        request.payload = Some(payload);
+
+        // The first dispatch holds the only permit while its body streams.
+        let (polled, first) = poll_once(client.dispatch(request, None));
+        assert!(polled.unwrap().is_not_ready());
+        let (polled, second) = poll_once(client.dispatch(signed_request("GET"), None));
+        assert!(polled.unwrap().is_not_ready());
+
+        drop(tx);
+        let (polled, _) = poll_once(first);
+        assert!(polled.unwrap().is_ready());
+        let (polled, _) = poll_once(second);
+        assert!(polled.unwrap().is_ready());
+    }
+
+    #[test]
+    fn chunked_response_streams_without_a_size_hint() {
+        let response = Response::builder()
+            .header("transfer-encoding", "chunked")
+            .body(MockBody::from_chunks(vec!["one", "two", "three"]))
+            .unwrap();
+        let (_, client) = respond_with(response);
+
+        let response = client.dispatch(signed_request("GET"), None).wait().unwrap();
+        assert_eq!(response.body.size_hint(), None);
+        let body = response.body.concat2().wait().unwrap();
+        assert_eq!(&body[..], b"onetwothree");
+    }
+
+    #[test]
+    fn oversized_headers_are_rejected() {
+        let mut request = signed_request("GET");
+        request.add_header("x-amz-meta-note", &"a".repeat(100));
+        let mock = MockService::new();
+        let client = HttpClient::builder(mock.clone())
+            .max_header_bytes(64)
+            .build();
+
+        let err = client
+            .dispatch(request, None)
+            .wait()
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("RequestBuildingError"), "{}", err);
+        assert!(err.contains("more than the limit of 64"), "{}", err);
+        assert!(mock.take_requests().is_empty());
+
+        let client = HttpClient::builder(mock.clone())
+            .max_header_bytes(1024)
+            .build();
+        let mut request = signed_request("GET");
+        request.add_header("x-amz-meta-note", &"a".repeat(100));
+        client.dispatch(request, None).wait().unwrap();
+        assert_eq!(mock.take_requests().len(), 1);
+    }
+
+    #[test]
+    fn custom_verb_is_mapped() {
+        let purge = Method::from_bytes(b"PURGE").unwrap();
+        let request = sent_request(
+            |b| b.method("PURGE", purge.clone()),
+            signed_request("PURGE"),
+        );
+        assert_eq!(request.method(), purge);
+
+        let err = build_error(signed_request("PURGE"));
+        assert!(err.contains("unsupported method: PURGE"), "{}", err);
+    }
+
+    #[test]
+    fn bogus_scheme_is_an_error() {
+        let mut request = signed_request("GET");
+        request.scheme = Some("ftp".to_string());
+
+        let err = build_error(request);
+        assert!(err.starts_with("RequestBuildingError"), "{}", err);
+        assert!(
+            err.contains("unsupported scheme \"ftp\", expected \"http\" or \"https\""),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn dispatch_http_keeps_the_version() {
+        let response = Response::builder()
+            .version(http::Version::HTTP_2)
+            .header("x-amz-request-id", "abc")
+            .body(MockBody::from("h2"))
+            .unwrap();
+        let (_, client) = respond_with(response);
+
+        let response = client
+            .dispatch_http(signed_request("GET"), None)
+            .wait()
+            .unwrap();
+        assert_eq!(response.version(), http::Version::HTTP_2);
+        assert_eq!(response.headers()["x-amz-request-id"], "abc");
+        let body = response.into_body().concat2().wait().unwrap();
+        assert_eq!(&body[..], b"h2");
+    }
+
+    #[test]
+    fn content_length_mismatch_is_an_error() {
+        let mut request = signed_request("PUT");
+        request.set_payload(Some(b"hello".to_vec()));
+        request.add_header("content-length", "4");
+        let mock = MockService::new();
+        let client = HttpClient::builder(mock.clone())
+            .check_content_length(true)
+            .build();
+
+        let err = client
+            .dispatch(request, None)
+            .wait()
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("RequestBuildingError"), "{}", err);
+        assert!(
+            err.ends_with("Content-Length \"4\" doesn't match the 5 byte payload"),
+            "{}",
+            err
+        );
+        assert!(mock.take_requests().is_empty());
+
+        let mut request = signed_request("PUT");
+        request.set_payload(Some(b"hello".to_vec()));
+        request.add_header("content-length", "5");
+        client.dispatch(request, None).wait().unwrap();
+    }
+
+    #[test]
+    fn shutdown_refuses_new_dispatches_and_drains_pending_ones() {
+        let client = HttpClient::builder(Echo).response_trailers(true).build();
+        let (tx, payload) = streaming_payload();
+        let mut request = signed_request("PUT");
+        request.payload = Some(payload);
+
+        let (polled, pending) = poll_once(client.dispatch(request, None));
+        assert!(polled.unwrap().is_not_ready());
+
+        let (polled, shutdown) = poll_once(client.shutdown());
+        assert!(polled.unwrap().is_not_ready());
+
+        let err = client
+            .dispatch(signed_request("GET"), None)
+            .wait()
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("ShutdownError"), "{}", err);
+
+        tx.unbounded_send(Bytes::from("done")).unwrap();
+        drop(tx);
+        let response = pending.wait().unwrap();
+        let body = response.body.concat2().wait().unwrap();
+        assert_eq!(&body[..], b"done");
+
+        let (polled, _) = poll_once(shutdown);
+        assert!(polled.unwrap().is_ready());
+    }
+
+    #[test]
+    fn shadow_gets_a_copy_pointed_at_its_authority() {
+        let primary = MockService::new();
+        primary.respond_with(Response::new(MockBody::from("primary")));
+        let shadow = MockService::new();
+        let client = HttpClient::builder(primary.clone())
+            .shadow(shadow.clone(), Authority::from_static("shadow.local:8080"))
+            .build();
+
+        let mut request = signed_request("PUT");
+        request.set_payload(Some(b"data".to_vec()));
+        let mut rt = Runtime::new().unwrap();
+        let response = rt.block_on(client.dispatch(request, None)).unwrap();
+        let body = rt.block_on(response.body.concat2()).unwrap();
+        assert_eq!(&body[..], b"primary");
+
+        // A streaming payload can't be copied.
+        let (tx, payload) = streaming_payload();
+        drop(tx);
+        let mut request = signed_request("PUT");
+        request.payload = Some(payload);
+        rt.block_on(client.dispatch(request, None)).unwrap();
+        rt.run().unwrap();
+
+        assert_eq!(primary.take_requests().len(), 2);
+        let copies = shadow.take_requests();
+        assert_eq!(copies.len(), 1);
+        assert_eq!(copies[0].uri(), "http://shadow.local:8080/bucket/key");
+        assert_eq!(
+            body_chunks(copies.into_iter().next().unwrap()),
+            vec![Bytes::from("data")]
+        );
+    }
+
+    #[test]
+    fn head_response_has_no_body() {
+        let response = Response::builder()
+            .header("content-length", "1024")
+            .body(MockBody::empty())
+            .unwrap();
+        let (_, client) = respond_with(response);
+
+        let response = client
+            .dispatch(signed_request("HEAD"), None)
+            .wait()
+            .unwrap();
+        assert_eq!(response.headers.get("content-length"), Some("1024"));
+        assert_eq!(response.body.size_hint(), Some(0));
+        assert!(response.body.concat2().wait().unwrap().is_empty());
+    }
+
+    /// A response body that is reset after its first chunk.
+    #[derive(Debug, Default)]
+    struct ResetMidway {
+        sent: bool,
+    }
+
+    impl Body for ResetMidway {
+        type Item = io::Cursor<Bytes>;
+        type Error = io::Error;
+
+        fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+            if self.sent {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionReset,
+                    "reset by peer",
+                ));
+            }
+            self.sent = true;
+            Ok(Async::Ready(Some(io::Cursor::new(Bytes::from("partial")))))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+            Ok(Async::Ready(None))
+        }
+    }
+
+    #[derive(Clone)]
+    struct Resetting;
+
+    impl Service<Request<RusotoBody>> for Resetting {
+        type Response = Response<ResetMidway>;
+        type Error = io::Error;
+        type Future = future::FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: Request<RusotoBody>) -> Self::Future {
+            future::ok(Response::new(ResetMidway::default()))
+        }
+    }
+
+    #[test]
+    fn body_error_mid_stream_follows_the_partial_data() {
+        let response = HttpClient::new(Resetting)
+            .dispatch(signed_request("GET"), None)
+            .wait()
+            .unwrap();
+        let mut body = response.body.wait();
+
+        assert_eq!(body.next().unwrap().unwrap(), Bytes::from("partial"));
+        let err = body.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(
+            err.to_string(),
+            "BodyError: reset by peer (after 7 bytes of the body)"
+        );
+    }
+
+    #[test]
+    fn error_formatter_shapes_the_message() {
+        let client = HttpClient::builder(Refused)
+            .error_formatter(|cx| {
+                format!(
+                    "[{}] {} {}: {}",
+                    cx.kind(),
+                    cx.method().unwrap_or("-"),
+                    cx.uri().unwrap_or("-"),
+                    cx.message()
+                )
+            })
+            .build();
+
+        let err = client
+            .dispatch(signed_request("GET"), None)
+            .wait()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[ConnectError] GET http://localhost:4566/bucket/key: connection refused"
+        );
+
+        let mut request = signed_request("GET");
+        request.add_header("bad name", "value");
+        let err = client
+            .dispatch(request, None)
+            .wait()
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("[RequestBuildingError] - -: "), "{}", err);
+    }
+
+    #[test]
+    fn validate_runs_the_dispatch_checks() {
+        let mock = MockService::new();
+        let client = HttpClient::new(mock.clone());
+        assert!(client.validate(&signed_request("GET")).is_ok());
+
+        let mut request = signed_request("GET");
+        request.add_header("bad name", "value");
+        let err = client.validate(&request).unwrap_err().to_string();
+        assert!(err.starts_with("RequestBuildingError"), "{}", err);
+        assert!(mock.take_requests().is_empty());
+    }
+
+    #[test]
+    fn collect_body_joins_the_chunks() {
+        let response = Response::builder()
+            .header("content-length", "11")
+            .body(MockBody::from_chunks(vec!["hello", " ", "world"]))
+            .unwrap();
+        let (_, client) = respond_with(response);
+
+        let response = client.dispatch(signed_request("GET"), None).wait().unwrap();
+        assert_eq!(
+            collect_body(response).wait().unwrap(),
+            Bytes::from("hello world")
+        );
+
+        // A single chunk is handed back as it is.
+        let chunk = Bytes::from(vec![1; 1024]);
+        let (_, client) = respond_with(Response::new(MockBody::from(chunk.clone())));
+        let response = client.dispatch(signed_request("GET"), None).wait().unwrap();
+        assert_eq!(
+            collect_body(response).wait().unwrap().as_ptr(),
+            chunk.as_ptr()
+        );
+    }
+
+    #[test]
+    fn status_policy_decides_success() {
+        let dispatch = |status: StatusCode| {
+            let mock = MockService::new();
+            mock.respond_with(
+                Response::builder()
+                    .status(status)
+                    .body(MockBody::empty())
+                    .unwrap(),
+            );
+            HttpClient::builder(mock)
+                .status_policy(|status| status.is_success())
+                .build()
+                .dispatch(signed_request("GET"), None)
+                .wait()
+        };
+
+        let response = dispatch(StatusCode::MULTI_STATUS).unwrap();
+        assert_eq!(response.status, StatusCode::MULTI_STATUS);
+
+        let err = dispatch(StatusCode::IM_A_TEAPOT).unwrap_err().to_string();
+        assert!(err.starts_with("StatusError"), "{}", err);
+        assert!(err.contains("server responded with 418"), "{}", err);
+    }
+
+    fn poll_chunk<S: Stream>(stream: &mut S) -> S::Item
+    where
+        S::Error: fmt::Debug,
+    {
+        match stream.poll().unwrap() {
+            Async::Ready(Some(item)) => item,
+            other => panic!("expected a chunk, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn body_stream_hands_on_bytes_without_copying() {
+        let first = Bytes::from(vec![1; 64 * 1024]);
+        let second = Bytes::from(vec![2; 64 * 1024]);
+        let body = MockBody::from_chunks(vec![first.clone(), second.clone()]);
+        let mut stream = BodyStream::new(body.into_buf_stream(), None, None, None, None);
+
+        assert_eq!(poll_chunk(&mut stream).as_ptr(), first.as_ptr());
+        assert_eq!(poll_chunk(&mut stream).as_ptr(), second.as_ptr());
+    }
+
+    #[test]
+    fn into_bytes_skips_the_consumed_part_of_a_cursor() {
+        let mut cursor = io::Cursor::new(Bytes::from(&b"hello world"[..]));
+        cursor.set_position(6);
+
+        assert_eq!(into_bytes(cursor), Bytes::from(&b"world"[..]));
+    }
+
+    #[test]
+    fn without_query_drops_presigned_signature() {
+        let uri: Uri = "https://bucket.s3.amazonaws.com/key?X-Amz-Signature=abc"
+            .parse()
+            .unwrap();
+        assert_eq!(without_query(&uri), "https://bucket.s3.amazonaws.com/key");
     }
 }
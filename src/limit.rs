@@ -0,0 +1,137 @@
+use futures::{
+    task::{self, Task},
+    Async,
+};
+use std::sync::{Arc, Mutex};
+
+/// Caps the number of dispatches that are in flight at once.
+pub(crate) struct Limit {
+    state: Mutex<State>,
+}
+
+/// Held by a dispatch while it is in flight, handed back on drop.
+pub(crate) struct Permit {
+    limit: Arc<Limit>,
+}
+
+struct State {
+    available: usize,
+    waiters: Vec<Task>,
+}
+
+impl Limit {
+    pub(crate) fn new(max: usize) -> Self {
+        Limit {
+            state: Mutex::new(State {
+                available: max,
+                waiters: Vec::new(),
+            }),
+        }
+    }
+
+    /// Take a permit, or register the current task to be notified once one
+    /// is handed back.
+    pub(crate) fn poll_acquire(self: &Arc<Self>) -> Async<Permit> {
+        let mut state = self.state.lock().expect("lock poisoned");
+        if state.available == 0 {
+            state.waiters.push(task::current());
+            return Async::NotReady;
+        }
+
+        state.available -= 1;
+        Async::Ready(Permit {
+            limit: self.clone(),
+        })
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let waiters = {
+            let mut state = self.limit.state.lock().expect("lock poisoned");
+            state.available += 1;
+            std::mem::replace(&mut state.waiters, Vec::new())
+        };
+
+        // Every waiter retries, the ones that lose the race register again.
+        for waiter in waiters {
+            waiter.notify();
+        }
+    }
+}
+
+/// Tracks the dispatches in flight so a client can stop taking new ones and
+/// wait for the rest to finish.
+pub(crate) struct Drain {
+    state: Mutex<DrainState>,
+}
+
+/// Held by a dispatch until it is done with, like a `Permit`.
+pub(crate) struct InFlight {
+    drain: Arc<Drain>,
+}
+
+struct DrainState {
+    closed: bool,
+    in_flight: usize,
+    waiters: Vec<Task>,
+}
+
+impl Drain {
+    pub(crate) fn new() -> Self {
+        Drain {
+            state: Mutex::new(DrainState {
+                closed: false,
+                in_flight: 0,
+                waiters: Vec::new(),
+            }),
+        }
+    }
+
+    /// Register a new dispatch, or `None` once the drain has started.
+    pub(crate) fn enter(self: &Arc<Self>) -> Option<InFlight> {
+        let mut state = self.state.lock().expect("lock poisoned");
+        if state.closed {
+            return None;
+        }
+
+        state.in_flight += 1;
+        Some(InFlight {
+            drain: self.clone(),
+        })
+    }
+
+    /// Refuse new dispatches from now on.
+    pub(crate) fn close(&self) {
+        self.state.lock().expect("lock poisoned").closed = true;
+    }
+
+    /// Check whether every registered dispatch is done, or register the
+    /// current task to be notified once they are.
+    pub(crate) fn poll_drained(&self) -> Async<()> {
+        let mut state = self.state.lock().expect("lock poisoned");
+        if state.in_flight == 0 {
+            return Async::Ready(());
+        }
+
+        state.waiters.push(task::current());
+        Async::NotReady
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let waiters = {
+            let mut state = self.drain.state.lock().expect("lock poisoned");
+            state.in_flight -= 1;
+            if state.in_flight > 0 {
+                return;
+            }
+            std::mem::replace(&mut state.waiters, Vec::new())
+        };
+
+        for waiter in waiters {
+            waiter.notify();
+        }
+    }
+}
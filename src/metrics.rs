@@ -0,0 +1,215 @@
+use http::StatusCode;
+use rusoto_core::{request::HttpDispatchError, signature::SignedRequest};
+
+/// Header used to tag a request for metrics.
+///
+/// Its value is passed to every `Metrics` callback for that request and the
+/// header is removed before the request is sent. Add it after signing, e.g.
+/// from a wrapping `DispatchSignedRequest`, so it isn't part of the
+/// signature.
+pub const TAG_HEADER: &str = "x-tower-rusoto-tag";
+
+/// Receives counter updates from an `HttpClient`.
+///
+/// Every dispatch is reported to `dispatched` and, once its future resolves,
+/// to exactly one of `completed` or `failed`. Timeouts are additionally
+/// reported to `timed_out`, as is a response body that outlives the total
+/// deadline after the dispatch completed.
+///
+/// Each callback gets the value of the request's `TAG_HEADER`, if it had
+/// one, so the counters can be split per tenant.
+pub trait Metrics: Send + Sync {
+    fn dispatched(&self, tag: Option<&str>) {
+        let _ = tag;
+    }
+
+    fn completed(&self, status: StatusCode, tag: Option<&str>) {
+        let _ = (status, tag);
+    }
+
+    fn timed_out(&self, tag: Option<&str>) {
+        let _ = tag;
+    }
+
+    fn failed(&self, tag: Option<&str>) {
+        let _ = tag;
+    }
+}
+
+pub(crate) fn tag(request: &SignedRequest) -> Option<String> {
+    request
+        .headers()
+        .get(TAG_HEADER)
+        .and_then(|values| values.first())
+        .map(|value| String::from_utf8_lossy(value).into_owned())
+}
+
+pub(crate) fn record(
+    metrics: &dyn Metrics,
+    outcome: Result<StatusCode, &HttpDispatchError>,
+    tag: Option<&str>,
+) {
+    match outcome {
+        Ok(status) => metrics.completed(status, tag),
+        Err(_) => metrics.failed(tag),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock::{MockBody, MockService},
+        DeadlineConfig, HttpClient, RusotoBody,
+    };
+    use bytes::Bytes;
+    use futures::{future, sync::mpsc, Async, Future, Poll, Stream};
+    use http::{Request, Response};
+    use rusoto_core::{
+        request::DispatchSignedRequest, signature::SignedRequestPayload, ByteStream, Region,
+    };
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use tokio::runtime::Runtime;
+    use tower_service::Service;
+
+    /// Records every callback as a line of text.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Recorder {
+        fn push(&self, event: String, tag: Option<&str>) {
+            let event = match tag {
+                Some(tag) => format!("{} [{}]", event, tag),
+                None => event,
+            };
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn take(&self) -> Vec<String> {
+            self.0.lock().unwrap().drain(..).collect()
+        }
+    }
+
+    impl Metrics for Recorder {
+        fn dispatched(&self, tag: Option<&str>) {
+            self.push("dispatched".to_string(), tag);
+        }
+
+        fn completed(&self, status: StatusCode, tag: Option<&str>) {
+            self.push(format!("completed {}", status.as_u16()), tag);
+        }
+
+        fn timed_out(&self, tag: Option<&str>) {
+            self.push("timed out".to_string(), tag);
+        }
+
+        fn failed(&self, tag: Option<&str>) {
+            self.push("failed".to_string(), tag);
+        }
+    }
+
+    fn request() -> SignedRequest {
+        SignedRequest::new("GET", "s3", &Region::UsEast1, "/key")
+    }
+
+    #[test]
+    fn dispatches_are_counted() {
+        let mock = MockService::new();
+        let unavailable = Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(MockBody::empty())
+            .unwrap();
+        mock.respond_with(Response::new(MockBody::empty()));
+        mock.respond_with(unavailable);
+        let recorder = Recorder::default();
+        let client = HttpClient::builder(mock).metrics(recorder.clone()).build();
+
+        client.dispatch(request(), None).wait().unwrap();
+        client.dispatch(request(), None).wait().unwrap();
+        let mut invalid = request();
+        invalid.add_header("bad name", "value");
+        client.dispatch(invalid, None).wait().unwrap_err();
+
+        assert_eq!(
+            recorder.take(),
+            vec![
+                "dispatched",
+                "completed 200",
+                "dispatched",
+                "completed 503",
+                "dispatched",
+                "failed",
+            ]
+        );
+    }
+
+    #[test]
+    fn tag_labels_the_counters_and_is_not_sent() {
+        let mock = MockService::new();
+        let recorder = Recorder::default();
+        let client = HttpClient::builder(mock.clone())
+            .metrics(recorder.clone())
+            .build();
+
+        let mut tagged = request();
+        tagged.add_header(TAG_HEADER, "tenant-a");
+        client.dispatch(tagged, None).wait().unwrap();
+
+        assert_eq!(
+            recorder.take(),
+            vec!["dispatched [tenant-a]", "completed 200 [tenant-a]"]
+        );
+        let requests = mock.take_requests();
+        assert!(!requests[0].headers().contains_key(TAG_HEADER));
+    }
+
+    /// Answers with the request body, so the response body stalls along with
+    /// the payload.
+    struct Echo;
+
+    impl Service<Request<RusotoBody>> for Echo {
+        type Response = Response<RusotoBody>;
+        type Error = io::Error;
+        type Future = future::FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, request: Request<RusotoBody>) -> Self::Future {
+            future::ok(Response::new(request.into_body()))
+        }
+    }
+
+    #[test]
+    fn body_past_the_total_deadline_is_counted_as_timed_out() {
+        let recorder = Recorder::default();
+        let deadlines = DeadlineConfig {
+            response_head: None,
+            total: Some(Duration::from_millis(20)),
+        };
+        let client = HttpClient::builder(Echo)
+            .deadlines(deadlines)
+            .metrics(recorder.clone())
+            .build();
+        let (tx, rx) = mpsc::unbounded();
+        let rx = rx.map_err(|()| io::Error::new(io::ErrorKind::Other, "sender failed"));
+        let mut request = SignedRequest::new("PUT", "s3", &Region::UsEast1, "/key");
+        request.payload = Some(SignedRequestPayload::Stream(ByteStream::new(rx)));
+
+        tx.unbounded_send(Bytes::from("first")).unwrap();
+        let mut rt = Runtime::new().unwrap();
+        let response = rt.block_on(client.dispatch(request, None)).unwrap();
+        rt.block_on(response.body.concat2()).unwrap_err();
+        drop(tx);
+
+        assert_eq!(
+            recorder.take(),
+            vec!["dispatched", "completed 200", "timed out"]
+        );
+    }
+}
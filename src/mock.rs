@@ -0,0 +1,160 @@
+//! A mock `HttpService` for exercising `HttpClient` without a network.
+
+use crate::RusotoBody;
+use bytes::Bytes;
+use futures::{future, Async, Poll};
+use http::{HeaderMap, Request, Response};
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{Arc, Mutex},
+};
+use tower_http::Body;
+use tower_service::Service;
+
+/// Records every request it is called with and replies with queued canned
+/// responses, or an empty `200 OK` once the queue is exhausted.
+///
+/// Clones share the same recorded requests and queued responses.
+#[derive(Clone, Default)]
+pub struct MockService {
+    inner: Arc<Mutex<Inner>>,
+}
+
+/// Response body of a `MockService`.
+#[derive(Debug, Default)]
+pub struct MockBody {
+    chunks: VecDeque<Bytes>,
+}
+
+#[derive(Default)]
+struct Inner {
+    requests: Vec<Request<RusotoBody>>,
+    responses: VecDeque<Response<MockBody>>,
+}
+
+impl MockService {
+    pub fn new() -> Self {
+        MockService::default()
+    }
+
+    /// Queue a response for the next call.
+    pub fn respond_with(&self, response: Response<MockBody>) {
+        self.inner.lock().unwrap().responses.push_back(response);
+    }
+
+    /// Take all the requests recorded so far.
+    pub fn take_requests(&self) -> Vec<Request<RusotoBody>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.requests.drain(..).collect()
+    }
+}
+
+impl Service<Request<RusotoBody>> for MockService {
+    type Response = Response<MockBody>;
+    type Error = io::Error;
+    type Future = future::FutureResult<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, request: Request<RusotoBody>) -> Self::Future {
+        let mut inner = self.inner.lock().unwrap();
+        inner.requests.push(request);
+
+        let response = inner
+            .responses
+            .pop_front()
+            .unwrap_or_else(|| Response::new(MockBody::empty()));
+
+        future::ok(response)
+    }
+}
+
+impl MockBody {
+    pub fn empty() -> Self {
+        MockBody::default()
+    }
+
+    /// Create a body that yields each of `chunks` from a separate `poll_buf`.
+    pub fn from_chunks<I>(chunks: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Bytes>,
+    {
+        MockBody {
+            chunks: chunks.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<&'static str> for MockBody {
+    fn from(body: &'static str) -> Self {
+        MockBody::from_chunks(Some(body))
+    }
+}
+
+impl From<Vec<u8>> for MockBody {
+    fn from(body: Vec<u8>) -> Self {
+        MockBody::from_chunks(Some(body))
+    }
+}
+
+impl From<Bytes> for MockBody {
+    fn from(body: Bytes) -> Self {
+        MockBody::from_chunks(Some(body))
+    }
+}
+
+impl Body for MockBody {
+    type Item = io::Cursor<Bytes>;
+    type Error = io::Error;
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        Ok(Async::Ready(self.chunks.pop_front().map(io::Cursor::new)))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+        Ok(Async::Ready(None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HttpClient;
+    use futures::{Future, Stream};
+    use http::{Method, StatusCode};
+    use rusoto_core::{request::DispatchSignedRequest, signature::SignedRequest, Region};
+
+    #[test]
+    fn records_requests_and_replies_in_order() {
+        let mock = MockService::new();
+        let not_found = Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(MockBody::from("missing"))
+            .unwrap();
+        mock.respond_with(not_found);
+        let client = HttpClient::new(mock.clone());
+
+        let request = SignedRequest::new("DELETE", "s3", &Region::UsWest2, "/bucket/key");
+        let response = client.dispatch(request, None).wait().unwrap();
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        assert_eq!(&response.body.concat2().wait().unwrap()[..], b"missing");
+
+        // Once the queue is empty every call gets an empty 200.
+        let request = SignedRequest::new("GET", "s3", &Region::UsWest2, "/bucket/key");
+        let response = client.dispatch(request, None).wait().unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+
+        let requests = mock.take_requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method(), Method::DELETE);
+        assert_eq!(
+            requests[0].uri(),
+            "https://s3.us-west-2.amazonaws.com/bucket/key"
+        );
+        assert!(mock.take_requests().is_empty());
+    }
+}
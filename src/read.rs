@@ -0,0 +1,81 @@
+use bytes::Bytes;
+use futures::{Async, Stream};
+use rusoto_core::{request::HttpResponse, ByteStream};
+use std::{cmp, fmt, io};
+use tokio_io::AsyncRead;
+
+/// Read the body of `response` as an `AsyncRead`, e.g. to copy an S3 download
+/// into a file.
+pub fn into_async_read(response: HttpResponse) -> BodyReader {
+    BodyReader {
+        body: response.body,
+        chunk: Bytes::new(),
+        done: false,
+    }
+}
+
+/// `AsyncRead` returned by `into_async_read`.
+pub struct BodyReader {
+    body: ByteStream,
+    chunk: Bytes,
+    done: bool,
+}
+
+impl io::Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            if self.done {
+                return Ok(0);
+            }
+
+            match self.body.poll()? {
+                Async::Ready(Some(chunk)) => self.chunk = chunk,
+                Async::Ready(None) => self.done = true,
+                Async::NotReady => return Err(io::ErrorKind::WouldBlock.into()),
+            }
+        }
+
+        let len = cmp::min(buf.len(), self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk.split_to(len));
+        Ok(len)
+    }
+}
+
+impl AsyncRead for BodyReader {}
+
+impl fmt::Debug for BodyReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BodyReader")
+            .field("buffered", &self.chunk.len())
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock::{MockBody, MockService},
+        HttpClient,
+    };
+    use futures::Future;
+    use http::Response;
+    use rusoto_core::{request::DispatchSignedRequest, signature::SignedRequest, Region};
+
+    #[test]
+    fn reads_a_multi_chunk_body() {
+        let mock = MockService::new();
+        let chunks = vec![vec![1; 10_000], vec![2; 3], vec![3; 20_000]];
+        mock.respond_with(Response::new(MockBody::from_chunks(chunks.clone())));
+        let client = HttpClient::new(mock);
+
+        let request = SignedRequest::new("GET", "s3", &Region::UsEast1, "/key");
+        let response = client.dispatch(request, None).wait().unwrap();
+        let (_, body) = tokio_io::io::read_to_end(into_async_read(response), Vec::new())
+            .wait()
+            .unwrap();
+
+        assert_eq!(body, chunks.concat());
+    }
+}
@@ -0,0 +1,334 @@
+//! A `tower-retry` policy for dispatching `SignedRequest`s.
+
+use crate::{error::Kind, try_clone_request};
+use futures::{Async, Future, Poll};
+use rand::Rng;
+use rusoto_core::{
+    request::{HttpDispatchError, HttpResponse},
+    signature::{SignedRequest, SignedRequestPayload},
+};
+use std::{
+    cmp,
+    time::{Duration, Instant},
+};
+use tokio_timer::Delay;
+use tower_retry::Policy;
+
+/// Retries idempotent requests that failed to dispatch or got a `5xx` back,
+/// backing off exponentially between attempts.
+///
+/// Only transient failures are retried: connecting, a closed connection, a
+/// timeout, other failures of the inner service, and `5xx` statuses failed by
+/// `treat_5xx_as_error` or a `status_policy`. Requests that could not be
+/// built, responses that could not be handed to rusoto, and requests that
+/// were refused by a circuit breaker or during shutdown fail right away. The
+/// failure is told by the prefix of the error message, so with an
+/// `error_formatter` only `5xx` responses are retried.
+///
+/// Requests whose payload is a `SignedRequestPayload::Stream` are never
+/// retried since the stream can't be rewound.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    remaining: usize,
+    attempt: u32,
+    backoff: Backoff,
+}
+
+/// Exponential backoff with optional full jitter.
+///
+/// The ceiling for attempt `n` is `base * 2^n` capped at `max`. With jitter
+/// enabled, which is the default, the delay is picked uniformly between zero
+/// and that ceiling so that clients failing together don't retry together.
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    jitter: bool,
+}
+
+/// Future that waits out the backoff before the next attempt.
+pub struct RetryDelay {
+    delay: Delay,
+    policy: Option<RetryPolicy>,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: usize) -> Self {
+        RetryPolicy {
+            remaining: max_retries,
+            attempt: 0,
+            backoff: Backoff::default(),
+        }
+    }
+
+    /// Set the delay before the first retry, doubled on every attempt after.
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.backoff.base = delay;
+        self
+    }
+
+    /// Set the upper bound on the delay between two attempts.
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.backoff.max = delay;
+        self
+    }
+
+    /// Replace the backoff used between attempts.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Backoff {
+            base,
+            max,
+            jitter: true,
+        }
+    }
+
+    /// Whether to randomize delays, defaults to `true`.
+    pub fn jitter(mut self, enabled: bool) -> Self {
+        self.jitter = enabled;
+        self
+    }
+
+    /// The upper bound of the delay before retry number `attempt`, counting
+    /// from zero.
+    pub fn ceiling(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::max_value());
+        let delay = self.base.checked_mul(factor).unwrap_or(self.max);
+        cmp::min(delay, self.max)
+    }
+
+    /// The delay before retry number `attempt`, counting from zero.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.delay_with(attempt, &mut rand::thread_rng())
+    }
+
+    /// Like `delay`, drawing the jitter from `rng`.
+    pub fn delay_with<R: Rng>(&self, attempt: u32, rng: &mut R) -> Duration {
+        let ceiling = self.ceiling(attempt);
+        if !self.jitter {
+            return ceiling;
+        }
+
+        let nanos = cmp::min(ceiling.as_nanos(), u128::from(u64::max_value())) as u64;
+        Duration::from_nanos(rng.gen_range(0, nanos.saturating_add(1)))
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::new(Duration::from_millis(50), Duration::from_secs(5))
+    }
+}
+
+impl Policy<SignedRequest, HttpResponse, HttpDispatchError> for RetryPolicy {
+    type Future = RetryDelay;
+
+    fn retry(
+        &self,
+        request: &SignedRequest,
+        result: Result<&HttpResponse, &HttpDispatchError>,
+    ) -> Option<Self::Future> {
+        if self.remaining == 0 || !is_idempotent(request.method()) {
+            return None;
+        }
+
+        if let Some(SignedRequestPayload::Stream(_)) = request.payload {
+            return None;
+        }
+
+        let transient = match result {
+            Ok(response) => response.status.is_server_error(),
+            Err(err) => is_transient(err),
+        };
+        if !transient {
+            return None;
+        }
+
+        let policy = RetryPolicy {
+            remaining: self.remaining - 1,
+            attempt: self.attempt + 1,
+            ..self.clone()
+        };
+
+        Some(RetryDelay {
+            delay: Delay::new(Instant::now() + self.backoff.delay(self.attempt)),
+            policy: Some(policy),
+        })
+    }
+
+    fn clone_request(&self, request: &SignedRequest) -> Option<SignedRequest> {
+        try_clone_request(request)
+    }
+}
+
+impl Future for RetryDelay {
+    type Item = RetryPolicy;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.delay.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            // A broken timer shouldn't prevent the retry, just skip the wait.
+            Ok(Async::Ready(())) | Err(_) => {}
+        }
+
+        let policy = self.policy.take().expect("polled after completion");
+        Ok(Async::Ready(policy))
+    }
+}
+
+fn is_transient(err: &HttpDispatchError) -> bool {
+    match Kind::of_dispatch_error(err) {
+        Some(Kind::Connect)
+        | Some(Kind::Closed)
+        | Some(Kind::Timeout)
+        | Some(Kind::Dispatch)
+        | Some(Kind::ServerStatus) => true,
+        // Mapping failures happen again on every attempt, and a
+        // `status_policy` rejecting e.g. a 418 won't change its mind.
+        _ => false,
+    }
+}
+
+fn is_idempotent(method: &str) -> bool {
+    match method {
+        "GET" | "HEAD" | "PUT" | "DELETE" | "OPTIONS" => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error,
+        mock::{MockBody, MockService},
+        HttpClient,
+    };
+    use futures::future;
+    use http::{Response, StatusCode};
+    use rand::{rngs::StdRng, SeedableRng};
+    use rusoto_core::Region;
+    use tokio::runtime::current_thread::Runtime;
+    use tower_retry::Retry;
+    use tower_service::Service;
+
+    /// A mock that answers `503` to the first `failures` requests.
+    fn flaky(failures: usize) -> MockService {
+        let mock = MockService::new();
+        for _ in 0..failures {
+            let unavailable = Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(MockBody::empty())
+                .unwrap();
+            mock.respond_with(unavailable);
+        }
+        mock
+    }
+
+    fn send(mock: &MockService, request: SignedRequest) -> HttpResponse {
+        let policy = RetryPolicy::new(2).base_delay(Duration::from_millis(1));
+        let mut service = Retry::new(policy, HttpClient::new(mock.clone()));
+
+        Runtime::new()
+            .unwrap()
+            .block_on(future::lazy(move || service.call(request)))
+            .unwrap()
+    }
+
+    #[test]
+    fn retries_a_flaky_get_until_it_succeeds() {
+        let mock = flaky(2);
+        let request = SignedRequest::new("GET", "s3", &Region::UsEast1, "/key");
+
+        let response = send(&mock, request);
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(mock.take_requests().len(), 3);
+    }
+
+    #[test]
+    fn post_is_not_retried() {
+        let mock = flaky(1);
+        let request = SignedRequest::new("POST", "s3", &Region::UsEast1, "/key");
+
+        let response = send(&mock, request);
+        assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(mock.take_requests().len(), 1);
+    }
+
+    #[test]
+    fn seeded_backoff_stays_within_the_ceiling() {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+        let mut rng = StdRng::seed_from_u64(7);
+
+        let delays = (0..8)
+            .map(|attempt| backoff.delay_with(attempt, &mut rng))
+            .collect::<Vec<_>>();
+        for (attempt, delay) in delays.iter().enumerate() {
+            assert!(*delay <= backoff.ceiling(attempt as u32), "{:?}", delay);
+        }
+        assert_eq!(backoff.ceiling(3), Duration::from_millis(800));
+        assert_eq!(backoff.ceiling(4), Duration::from_secs(1));
+
+        // The same seed gives the same delays.
+        let mut rng = StdRng::seed_from_u64(7);
+        let again = (0..8)
+            .map(|attempt| backoff.delay_with(attempt, &mut rng))
+            .collect::<Vec<_>>();
+        assert_eq!(delays, again);
+
+        let fixed = backoff.jitter(false);
+        assert_eq!(fixed.delay_with(2, &mut rng), Duration::from_millis(400));
+    }
+
+    fn retries(err: HttpDispatchError) -> bool {
+        let request = SignedRequest::new("GET", "s3", &Region::UsEast1, "/key");
+        RetryPolicy::new(1).retry(&request, Err(&err)).is_some()
+    }
+
+    #[test]
+    fn retries_transient_failures() {
+        assert!(retries(error::new(Kind::Connect, "connection refused")));
+        assert!(retries(error::new(Kind::Closed, "connection reset")));
+        assert!(retries(error::new(Kind::Timeout, "request timed out")));
+        assert!(retries(error::new(Kind::Dispatch, "broken")));
+        assert!(retries(error::new(
+            Kind::ServerStatus,
+            "GET /key: server responded with 503 Service Unavailable"
+        )));
+    }
+
+    #[test]
+    fn does_not_retry_permanent_failures() {
+        assert!(!retries(error::new(Kind::Build, "invalid header")));
+        assert!(!retries(error::new(
+            Kind::Shutdown,
+            "the client is shutting down"
+        )));
+        assert!(!retries(error::new(Kind::CircuitOpen, "circuit open")));
+        assert!(!retries(error::new(
+            Kind::Mapping,
+            "GET /key: response header x-amz-meta-raw is not valid UTF-8"
+        )));
+        assert!(!retries(error::new(
+            Kind::Status,
+            "GET /key: server responded with 418 I'm a teapot"
+        )));
+        assert!(!retries(HttpDispatchError::new(
+            "custom message".to_string()
+        )));
+    }
+
+    #[test]
+    fn does_not_retry_non_idempotent_requests() {
+        let request = SignedRequest::new("POST", "s3", &Region::UsEast1, "/key");
+        let err = error::new(Kind::Connect, "connection refused");
+        assert!(RetryPolicy::new(1).retry(&request, Err(&err)).is_none());
+    }
+}
@@ -0,0 +1,90 @@
+use crate::RusotoBody;
+use futures::{Async, Future, Poll};
+use http::{uri::Authority, Request, Uri};
+use std::sync::{Arc, Mutex};
+use tokio_executor::Executor;
+use tower_http::HttpService;
+
+/// Hands a copy of a request to the shadow service.
+pub(crate) type Mirror = Arc<dyn Fn(Request<RusotoBody>) + Send + Sync>;
+
+/// Erase `service` into a `Mirror` that points each request at `authority`,
+/// spawns it onto the default executor and discards the outcome.
+pub(crate) fn mirror<S>(service: S, authority: Authority) -> Mirror
+where
+    S: HttpService<RusotoBody> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    let service = Mutex::new(service);
+    Arc::new(move |mut request: Request<RusotoBody>| {
+        let mut parts = request.uri().clone().into_parts();
+        parts.authority = Some(authority.clone());
+        match Uri::from_parts(parts) {
+            Ok(uri) => *request.uri_mut() = uri,
+            // Never send the copy to the production host.
+            Err(_) => return,
+        }
+
+        let service = service.lock().expect("lock poisoned").clone();
+        let fut = Oneshot {
+            state: State::NotReady(service, Some(request)),
+        };
+        // Without an executor there is nowhere to run it, and the copy is
+        // only best effort anyway.
+        let _ = tokio_executor::DefaultExecutor::current().spawn(Box::new(fut));
+    })
+}
+
+/// Clone a request whose body is buffered, or `None` if the body can only be
+/// read once.
+///
+/// Extensions aren't `Clone` and are left out of the copy.
+pub(crate) fn try_clone(request: &Request<RusotoBody>) -> Option<Request<RusotoBody>> {
+    let body = request.body().try_clone()?;
+    let mut copy = Request::new(body);
+    *copy.method_mut() = request.method().clone();
+    *copy.uri_mut() = request.uri().clone();
+    *copy.version_mut() = request.version();
+    *copy.headers_mut() = request.headers().clone();
+    Some(copy)
+}
+
+struct Oneshot<S>
+where
+    S: HttpService<RusotoBody>,
+{
+    state: State<S>,
+}
+
+enum State<S>
+where
+    S: HttpService<RusotoBody>,
+{
+    NotReady(S, Option<Request<RusotoBody>>),
+    Pending(S::Future),
+}
+
+impl<S> Future for Oneshot<S>
+where
+    S: HttpService<RusotoBody>,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match &mut self.state {
+                State::NotReady(service, request) => {
+                    futures::try_ready!(service.poll_ready().map_err(drop));
+                    let request = request.take().expect("polled after completion");
+                    self.state = State::Pending(service.call(request));
+                }
+                State::Pending(fut) => {
+                    // The response, body included, is dropped unread.
+                    futures::try_ready!(fut.poll().map_err(drop));
+                    return Ok(Async::Ready(()));
+                }
+            }
+        }
+    }
+}
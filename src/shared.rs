@@ -0,0 +1,182 @@
+use futures::{task::Task, Async, Poll};
+use std::sync::{Arc, Mutex};
+use tower_service::Service;
+
+/// Shares a service that isn't `Clone` between the clones `HttpClient` makes,
+/// by putting it behind a lock.
+///
+/// Every `poll_ready` and `call` takes the lock, so concurrent dispatches
+/// contend on it; this suits services whose calls return quickly with a
+/// future, such as a single connection. Once the inner service is ready, the
+/// clone that polled it holds on to that readiness until it calls the
+/// service or is dropped, and the other clones wait for it. For a service
+/// that should be driven on its own task, such as a `tower-balance`
+/// balancer, use a `tower-buffer` `Buffer` instead.
+pub struct Shared<T> {
+    inner: Arc<Mutex<State<T>>>,
+    reserved: bool,
+}
+
+struct State<T> {
+    service: T,
+    reserved: bool,
+    waiting: Vec<Task>,
+}
+
+impl<T> Shared<T> {
+    pub fn new(inner: T) -> Self {
+        let state = State {
+            service: inner,
+            reserved: false,
+            waiting: Vec::new(),
+        };
+        Shared {
+            inner: Arc::new(Mutex::new(state)),
+            reserved: false,
+        }
+    }
+}
+
+impl<T> State<T> {
+    /// Give up the readiness a clone held, waking the clones waiting for it.
+    fn release(&mut self) {
+        self.reserved = false;
+        for task in self.waiting.drain(..) {
+            task.notify();
+        }
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Shared {
+            inner: self.inner.clone(),
+            reserved: false,
+        }
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        if self.reserved {
+            if let Ok(mut state) = self.inner.lock() {
+                state.release();
+            }
+        }
+    }
+}
+
+impl<T, R> Service<R> for Shared<T>
+where
+    T: Service<R>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        if self.reserved {
+            return Ok(Async::Ready(()));
+        }
+
+        let mut state = self.inner.lock().expect("lock poisoned");
+        if state.reserved {
+            state.waiting.push(futures::task::current());
+            return Ok(Async::NotReady);
+        }
+        futures::try_ready!(state.service.poll_ready());
+        state.reserved = true;
+        self.reserved = true;
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        assert!(self.reserved, "called before poll_ready");
+        self.reserved = false;
+
+        let mut state = self.inner.lock().expect("lock poisoned");
+        state.release();
+        state.service.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock::{MockBody, MockService},
+        HttpClient, RusotoBody,
+    };
+    use futures::{future, Async, Future};
+    use http::{Request, Response};
+    use rusoto_core::{request::DispatchSignedRequest, signature::SignedRequest, Region};
+    use std::io;
+
+    /// Counts its calls, which clones of it would each do on their own.
+    struct Counting {
+        calls: usize,
+        mock: MockService,
+    }
+
+    impl Service<Request<RusotoBody>> for Counting {
+        type Response = Response<MockBody>;
+        type Error = io::Error;
+        type Future = future::FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, request: Request<RusotoBody>) -> Self::Future {
+            self.calls += 1;
+            self.mock.call(request)
+        }
+    }
+
+    #[test]
+    fn dispatches_on_a_shared_service() {
+        let mock = MockService::new();
+        let shared = Shared::new(Counting {
+            calls: 0,
+            mock: mock.clone(),
+        });
+        let client = HttpClient::new(shared.clone());
+
+        for _ in 0..3 {
+            let request = SignedRequest::new("GET", "s3", &Region::UsEast1, "/key");
+            client.dispatch(request, None).wait().unwrap();
+        }
+
+        assert_eq!(shared.inner.lock().unwrap().service.calls, 3);
+        assert_eq!(mock.take_requests().len(), 3);
+    }
+
+    fn is_ready(shared: &mut Shared<Counting>) -> bool {
+        Service::<Request<RusotoBody>>::poll_ready(shared)
+            .unwrap()
+            .is_ready()
+    }
+
+    #[test]
+    fn readiness_is_held_until_the_call() {
+        let shared = Shared::new(Counting {
+            calls: 0,
+            mock: MockService::new(),
+        });
+        let (mut first, mut second) = (shared.clone(), shared.clone());
+
+        future::lazy(move || {
+            assert!(is_ready(&mut first));
+            assert!(!is_ready(&mut second));
+            let _ = first.call(Request::new(RusotoBody::empty()));
+            assert!(is_ready(&mut second));
+
+            // Dropping a clone gives up its readiness as well.
+            drop(second);
+            assert!(is_ready(&mut first));
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+}
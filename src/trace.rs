@@ -0,0 +1,92 @@
+use http::StatusCode;
+use rusoto_core::{request::HttpDispatchError, signature::SignedRequest};
+use tracing::{field, Span};
+
+pub(crate) fn span(request: &SignedRequest) -> Span {
+    tracing::debug_span!(
+        "dispatch",
+        method = %request.method(),
+        host = %request.hostname(),
+        path = %request.canonical_path(),
+        status = field::Empty,
+        error = field::Empty,
+    )
+}
+
+/// Record the outcome of a dispatch once it has resolved.
+pub(crate) fn record(span: &Span, outcome: Result<StatusCode, &HttpDispatchError>) {
+    match outcome {
+        Ok(status) => {
+            span.record("status", &status.as_u16());
+        }
+        Err(e) => {
+            span.record("error", &field::display(e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{mock::MockService, HttpClient};
+    use futures::Future;
+    use rusoto_core::{signature::SignedRequest, DispatchSignedRequest, Region};
+    use std::{
+        fmt,
+        sync::{Arc, Mutex},
+    };
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Id, Metadata, Subscriber,
+    };
+
+    /// Keeps every field recorded on a span after it was created.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        fields: Arc<Mutex<Vec<(String, String)>>>,
+    }
+
+    struct Collect<'a>(&'a mut Vec<(String, String)>);
+
+    impl Visit for Collect<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0
+                .push((field.name().to_string(), format!("{:?}", value)));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &span::Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _: &Id, values: &span::Record<'_>) {
+            values.record(&mut Collect(&mut self.fields.lock().unwrap()));
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn status_is_recorded_on_the_span() {
+        let recorder = Recorder::default();
+        let client = HttpClient::new(MockService::new());
+
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let request = SignedRequest::new("GET", "s3", &Region::UsEast1, "/key");
+            client.dispatch(request, None).wait().unwrap();
+        });
+
+        let fields = recorder.fields.lock().unwrap();
+        assert_eq!(*fields, vec![("status".to_string(), "200".to_string())]);
+    }
+}
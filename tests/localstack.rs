@@ -0,0 +1,106 @@
+//! Integration tests against a LocalStack S3.
+//!
+//! They only build with the `localstack` feature and expect LocalStack to be
+//! reachable at `LOCALSTACK_ENDPOINT`, `http://localhost:4566` by default:
+//!
+//! ```text
+//! docker run -d -p 4566:4566 localstack/localstack
+//! cargo test --features localstack --test localstack
+//! ```
+
+use bytes::Bytes;
+use futures::{stream, Future, Stream};
+use rusoto_core::{credential::StaticProvider, Region, RusotoError};
+use rusoto_s3::{
+    CreateBucketError, CreateBucketRequest, GetObjectRequest, PutObjectRequest, S3Client,
+    StreamingBody, S3,
+};
+use std::env;
+use tokio::runtime::Runtime;
+use tower_rusoto::HttpClient;
+
+const BUCKET: &str = "tower-rusoto";
+
+fn client() -> S3Client {
+    let endpoint =
+        env::var("LOCALSTACK_ENDPOINT").unwrap_or_else(|_| "http://localhost:4566".to_string());
+    let region = Region::Custom {
+        name: "us-east-1".to_string(),
+        endpoint,
+    };
+    let credentials = StaticProvider::new_minimal("test".to_string(), "test".to_string());
+
+    S3Client::new_with(HttpClient::http(), credentials, region)
+}
+
+fn create_bucket(rt: &mut Runtime, s3: &S3Client) {
+    let request = CreateBucketRequest {
+        bucket: BUCKET.to_string(),
+        ..Default::default()
+    };
+
+    match rt.block_on(s3.create_bucket(request)) {
+        Ok(_) | Err(RusotoError::Service(CreateBucketError::BucketAlreadyOwnedByYou(_))) => {}
+        Err(e) => panic!("creating the bucket failed: {}", e),
+    }
+}
+
+fn get_object(rt: &mut Runtime, s3: &S3Client, key: &str) -> Vec<u8> {
+    let request = GetObjectRequest {
+        bucket: BUCKET.to_string(),
+        key: key.to_string(),
+        ..Default::default()
+    };
+
+    let output = rt.block_on(s3.get_object(request)).expect("get_object");
+    let body = output.body.expect("object has a body");
+    rt.block_on(body.concat2())
+        .expect("reading the body")
+        .to_vec()
+}
+
+/// Enough to be split into several chunks on the way up and down.
+fn payload() -> Vec<u8> {
+    (0..200 * 1024).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn put_and_get_buffered() {
+    let mut rt = Runtime::new().unwrap();
+    let s3 = client();
+    create_bucket(&mut rt, &s3);
+
+    let data = payload();
+    let request = PutObjectRequest {
+        bucket: BUCKET.to_string(),
+        key: "buffered".to_string(),
+        body: Some(data.clone().into()),
+        ..Default::default()
+    };
+    rt.block_on(s3.put_object(request)).expect("put_object");
+
+    assert_eq!(get_object(&mut rt, &s3, "buffered"), data);
+}
+
+#[test]
+fn put_and_get_streamed() {
+    let mut rt = Runtime::new().unwrap();
+    let s3 = client();
+    create_bucket(&mut rt, &s3);
+
+    let data = payload();
+    let chunks = data.chunks(16 * 1024).map(Bytes::from).collect::<Vec<_>>();
+    let request = PutObjectRequest {
+        bucket: BUCKET.to_string(),
+        key: "streamed".to_string(),
+        content_length: Some(data.len() as i64),
+        body: Some(StreamingBody::new_with_size(
+            stream::iter_ok(chunks),
+            data.len(),
+        )),
+        ..Default::default()
+    };
+    rt.block_on(s3.put_object(request)).expect("put_object");
+
+    assert_eq!(get_object(&mut rt, &s3, "streamed"), data);
+}